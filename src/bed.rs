@@ -1,4 +1,104 @@
 use std::cmp::{max, min};
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ParseError {
+    #[error("{name}: CDS {cds_start}-{cds_end} is outside the transcript {tx_start}-{tx_end}")]
+    CdsOutOfBounds {
+        name: String,
+        cds_start: u32,
+        cds_end: u32,
+        tx_start: u32,
+        tx_end: u32,
+    },
+    #[error("{name}: CDS boundary {pos} does not fall inside any exon")]
+    CdsOutsideExons { name: String, pos: u32 },
+    #[error("{name}: position {pos} is not inside any exon")]
+    NotInExons { name: String, pos: u32 },
+    #[error("{name}: can't move {pos} by {dist}")]
+    CannotMove { name: String, pos: u32, dist: i32 },
+    #[error("{name}: blockCount is {expected} but {field} lists {found} blocks")]
+    BlockCount {
+        name: String,
+        field: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    #[error("{name}: block {block} has size 0")]
+    EmptyBlock { name: String, block: usize },
+    #[error("{name}: transcript end {tx_end} is not after its start {tx_start}")]
+    EmptyTranscript {
+        name: String,
+        tx_start: u32,
+        tx_end: u32,
+    },
+    #[error("{name}: block {block} lies outside the transcript {tx_start}-{tx_end}")]
+    BlockOutOfBounds {
+        name: String,
+        block: usize,
+        tx_start: u32,
+        tx_end: u32,
+    },
+    #[error("{0}")]
+    Malformed(&'static str),
+}
+
+impl From<&'static str> for ParseError {
+    fn from(message: &'static str) -> Self {
+        ParseError::Malformed(message)
+    }
+}
+
+/// Where the transcript name of a BED line comes from: the 1-based column
+/// `field` (the name column if None), optionally split on `split` keeping
+/// its 1-based `index`th part. The default keeps the name column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameSource {
+    pub field: Option<usize>,
+    pub split: Option<char>,
+    pub index: usize,
+}
+
+impl Default for NameSource {
+    fn default() -> Self {
+        NameSource {
+            field: None,
+            split: None,
+            index: 1,
+        }
+    }
+}
+
+impl NameSource {
+    /// Name taken from `line`, None when the name column is kept as is
+    pub fn extract(&self, line: &str, whitespace: bool) -> Result<Option<String>, ParseError> {
+        if self.field.is_none() && self.split.is_none() {
+            return Ok(None);
+        }
+
+        let field = self.field.unwrap_or(4) - 1;
+        let value = if whitespace {
+            line.split_whitespace().nth(field)
+        } else {
+            line.split('\t').nth(field)
+        }
+        .ok_or("Name field is missing")?;
+
+        match self.split {
+            Some(split) => Ok(Some(
+                value
+                    .split(split)
+                    .nth(self.index - 1)
+                    .ok_or("Name has fewer parts than --name-index")?
+                    .to_string(),
+            )),
+            None => Ok(Some(value.to_string())),
+        }
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct BedRecord {
@@ -6,19 +106,56 @@ pub struct BedRecord {
     pub tx_start: u32,
     pub tx_end: u32,
     pub name: String,
+    /// BED score as written; "0" is the conventional unset value
+    pub score: String,
     pub strand: String,
     pub cds_start: u32,
     pub cds_end: u32,
     pub exon_count: u16,
     pub exon_start: Vec<u32>,
     pub exon_end: Vec<u32>,
+    /// itemRgb, if set to anything other than 0
+    pub item_rgb: Option<String>,
+    /// name2 (13th column, the gene symbol of bigGenePred/BED15), if present
+    pub name2: Option<String>,
+    /// exonFrames (16th column of bigGenePred), if present and one valid
+    /// frame per exon; only checked by `frame_problems`, never trusted
+    pub exon_frames: Option<Vec<i16>>,
+    /// Columns after name2 (cdsStartStat, cdsEndStat, exonFrames, ... of
+    /// bigGenePred) as written, so `Display` gives them back unchanged
+    pub extra: Vec<String>,
 }
 
 impl BedRecord {
-    pub fn parse(line: &str) -> Result<BedRecord, &'static str> {
-        let fields: Vec<&str> = line.split('\t').collect();
+    /// Parses a tab-separated BED12 line; blocks become absolute, 0-based
+    /// half-open exon coordinates.
+    ///
+    /// ```
+    /// use bed2gff::BedRecord;
+    ///
+    /// let record = BedRecord::parse("chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,")
+    ///     .unwrap();
+    ///
+    /// assert_eq!(record.exon_count, 2);
+    /// assert_eq!(record.exon_start, [100, 300]);
+    /// assert_eq!(record.exon_end, [200, 500]);
+    /// assert_eq!((record.cds_start, record.cds_end), (150, 450));
+    /// ```
+    pub fn parse(line: &str) -> Result<BedRecord, ParseError> {
+        BedRecord::from_fields(&line.split('\t').collect::<Vec<_>>())
+    }
+
+    /// Parses a BED12 line whose fields are separated by any run of spaces
+    /// or tabs, as hand-edited files sometimes are.
+    pub fn parse_whitespace(line: &str) -> Result<BedRecord, ParseError> {
+        BedRecord::from_fields(&line.split_whitespace().collect::<Vec<_>>())
+    }
+
+    fn from_fields(fields: &[&str]) -> Result<BedRecord, ParseError> {
         if fields.len() < 12 {
-            return Err("Bed line has less than 12 fields and cannot be parsed into a BedRecord");
+            return Err(
+                "Bed line has less than 12 fields and cannot be parsed into a BedRecord".into(),
+            );
         }
 
         let chrom = fields[0].to_string();
@@ -30,27 +167,63 @@ impl BedRecord {
         let tx_end = get(fields[2])?;
         let cds_start = get(fields[6])?;
         let cds_end = get(fields[7])?;
-        let exon_count = get(fields[9])? as u16;
+        let exon_count = u16::try_from(get(fields[9])?).map_err(|_| "blockCount is too large")?;
+
+        if tx_end <= tx_start {
+            return Err(ParseError::EmptyTranscript {
+                name,
+                tx_start,
+                tx_end,
+            });
+        }
 
         let group = |field: &str| -> Result<Vec<u32>, &'static str> {
             field
                 .split(',')
-                .filter_map(|num| {
-                    if !num.is_empty() {
-                        Some(num.parse::<u32>().expect("Cannot parse number"))
-                    } else {
-                        None
-                    }
-                })
-                .map(|num| Ok(num))
+                .filter(|num| !num.is_empty())
+                .map(|num| num.parse::<u32>().map_err(|_| "Cannot parse block field"))
                 .collect()
         };
 
         let exon_start = group(fields[11])?;
         let exon_end = group(fields[10])?;
 
-        if exon_start.len() != exon_end.len() {
-            return Err("Exon start and end vectors have different lengths");
+        for (field, found) in [
+            ("blockSizes", exon_end.len()),
+            ("blockStarts", exon_start.len()),
+        ] {
+            if found != exon_count as usize {
+                return Err(ParseError::BlockCount {
+                    name,
+                    field,
+                    expected: exon_count as usize,
+                    found,
+                });
+            }
+        }
+
+        if let Some(idx) = exon_end.iter().position(|&size| size == 0) {
+            return Err(ParseError::EmptyBlock {
+                name,
+                block: idx + 1,
+            });
+        }
+
+        // blocks must lie within [tx_start, tx_end), checked before the
+        // sums below can overflow
+        if let Some(idx) = exon_start
+            .iter()
+            .zip(&exon_end)
+            .position(|(&start, &size)| {
+                tx_start as u64 + start as u64 + size as u64 > tx_end as u64
+            })
+        {
+            return Err(ParseError::BlockOutOfBounds {
+                name,
+                block: idx + 1,
+                tx_start,
+                tx_end,
+            });
         }
 
         let exon_starts: Vec<u32> = exon_start.iter().map(|&s| s + tx_start).collect();
@@ -62,18 +235,184 @@ impl BedRecord {
 
         Ok(BedRecord {
             chrom: chrom.to_string(),
-            tx_start: tx_start,
-            tx_end: tx_end,
+            tx_start,
+            tx_end,
             name: name.to_string(),
+            score: fields[4].to_string(),
             strand: strand.to_string(),
-            cds_start: cds_start,
-            cds_end: cds_end,
-            exon_count: exon_count,
+            cds_start,
+            cds_end,
+            exon_count,
             exon_start: exon_starts,
             exon_end: exon_ends,
+            item_rgb: Some(fields[8])
+                .filter(|rgb| !rgb.is_empty() && *rgb != "0")
+                .map(str::to_string),
+            name2: fields
+                .get(12)
+                .filter(|name2| !name2.is_empty())
+                .map(|name2| name2.to_string()),
+            exon_frames: fields.get(15).and_then(|field| {
+                field
+                    .split(',')
+                    .filter(|frame| !frame.is_empty())
+                    .map(|frame| frame.parse::<i16>().ok().filter(|f| (-1..=2).contains(f)))
+                    .collect::<Option<Vec<_>>>()
+                    .filter(|frames| frames.len() == exon_count as usize)
+            }),
+            extra: fields
+                .iter()
+                .skip(13)
+                .map(|field| field.to_string())
+                .collect(),
+        })
+    }
+
+    /// Parses a BED6 line (chrom, start, end, name, score, strand) as a
+    /// single-exon transcript without CDS. Lines with 12 or more fields are
+    /// parsed as BED12.
+    pub fn parse_bed6(line: &str) -> Result<BedRecord, ParseError> {
+        BedRecord::bed6_from_fields(&line.split('\t').collect::<Vec<_>>())
+    }
+
+    /// `parse_bed6` for lines separated by any run of spaces or tabs
+    pub fn parse_bed6_whitespace(line: &str) -> Result<BedRecord, ParseError> {
+        BedRecord::bed6_from_fields(&line.split_whitespace().collect::<Vec<_>>())
+    }
+
+    fn bed6_from_fields(fields: &[&str]) -> Result<BedRecord, ParseError> {
+        if fields.len() >= 12 {
+            return BedRecord::from_fields(fields);
+        }
+        if fields.len() < 6 {
+            return Err(
+                "Bed line has less than 6 fields and cannot be parsed into a BedRecord".into(),
+            );
+        }
+
+        let get = |field: &str| field.parse::<u32>().map_err(|_| "Cannot parse field");
+        let tx_start = get(fields[1])?;
+        let tx_end = get(fields[2])?;
+        if tx_end <= tx_start {
+            return Err(ParseError::EmptyTranscript {
+                name: fields[3].to_string(),
+                tx_start,
+                tx_end,
+            });
+        }
+
+        Ok(BedRecord {
+            chrom: fields[0].to_string(),
+            tx_start,
+            tx_end,
+            name: fields[3].to_string(),
+            score: fields[4].to_string(),
+            strand: fields[5].to_string(),
+            cds_start: tx_start,
+            cds_end: tx_start,
+            exon_count: 1,
+            exon_start: vec![tx_start],
+            exon_end: vec![tx_end],
+            item_rgb: None,
+            name2: None,
+            exon_frames: None,
+            extra: Vec::new(),
         })
     }
 
+    /// Checks that the CDS (thickStart/thickEnd) lies within the transcript
+    /// and that both of its boundaries fall inside an exon. Non-coding
+    /// records are always valid.
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if self.cds_start >= self.cds_end {
+            return Ok(());
+        }
+
+        if self.cds_start < self.tx_start || self.cds_end > self.tx_end {
+            return Err(ParseError::CdsOutOfBounds {
+                name: self.name.clone(),
+                cds_start: self.cds_start,
+                cds_end: self.cds_end,
+                tx_start: self.tx_start,
+                tx_end: self.tx_end,
+            });
+        }
+
+        let exons = || self.exon_start.iter().zip(&self.exon_end);
+        if !exons().any(|(&s, &e)| s <= self.cds_start && self.cds_start < e) {
+            return Err(ParseError::CdsOutsideExons {
+                name: self.name.clone(),
+                pos: self.cds_start,
+            });
+        }
+        if !exons().any(|(&s, &e)| s < self.cds_end && self.cds_end <= e) {
+            return Err(ParseError::CdsOutsideExons {
+                name: self.name.clone(),
+                pos: self.cds_end,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Moves CDS boundaries lying in introns or beyond the transcript to the
+    /// nearest exon boundary inside the CDS. A CDS without any exonic base
+    /// is dropped, leaving the record non-coding.
+    pub fn clamp_cds(&mut self) {
+        let exons = self.exon_start.iter().zip(&self.exon_end);
+        let start = exons
+            .clone()
+            .filter(|(_, &e)| e > self.cds_start)
+            .map(|(&s, _)| max(s, self.cds_start))
+            .min();
+        let end = exons
+            .filter(|(&s, _)| s < self.cds_end)
+            .map(|(_, &e)| min(e, self.cds_end))
+            .max();
+
+        match (start, end) {
+            (Some(start), Some(end)) if start < end => {
+                self.cds_start = start;
+                self.cds_end = end;
+            }
+            _ => self.cds_end = self.cds_start,
+        }
+    }
+
+    /// Length of the longest gap between consecutive exons, 0 for a single
+    /// exon
+    pub fn longest_intron(&self) -> u32 {
+        self.exon_end
+            .iter()
+            .zip(self.exon_start.iter().skip(1))
+            .map(|(end, next)| next.saturating_sub(*end))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Summed length of the exons
+    pub fn exon_length(&self) -> u32 {
+        self.exon_start
+            .iter()
+            .zip(&self.exon_end)
+            .map(|(start, end)| end - start)
+            .sum()
+    }
+
+    /// Summed length of the exon parts between thickStart and thickEnd, 0
+    /// for non-coding transcripts
+    pub fn cds_length(&self) -> u32 {
+        self.exon_start
+            .iter()
+            .zip(&self.exon_end)
+            .map(|(start, end)| min(*end, self.cds_end).saturating_sub(max(*start, self.cds_start)))
+            .sum()
+    }
+
+    /// Frame of each exon, always recomputed from the coding length seen so
+    /// far in translation order; -1 for exons without CDS. Stored bigGenePred
+    /// `exon_frames` are deliberately ignored for output phases and only
+    /// compared by `--cds-frame-check`.
     pub fn get_frames(&self) -> Vec<i16> {
         let mut exon_frames: Vec<i16> = vec![0; self.exon_count as usize];
         let mut cds: u32 = 0;
@@ -98,6 +437,98 @@ impl BedRecord {
 
         exon_frames
     }
+
+    /// Checks the stored exonFrames, if any, against the coding length read
+    /// before each CDS exon in translation order, and that the CDS ends on a
+    /// whole codon. Exons are numbered as in the GFF output. A mismatch hints
+    /// at a frameshift or bad frame annotation.
+    pub fn frame_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut cds: u32 = 0;
+
+        let exons = (0..self.exon_count as usize).collect::<Vec<_>>();
+        let exons = if self.strand == "-" {
+            exons.into_iter().rev().collect()
+        } else {
+            exons
+        };
+
+        for (number, exon) in exons.into_iter().enumerate() {
+            let cds_exon_start = max(self.exon_start[exon], self.cds_start);
+            let cds_exon_end = min(self.exon_end[exon], self.cds_end);
+            if cds_exon_start >= cds_exon_end {
+                continue;
+            }
+
+            let expected = (cds % 3) as i16;
+            if let Some(frame) = self.exon_frames.as_ref().map(|frames| frames[exon]) {
+                if frame != expected {
+                    problems.push(format!(
+                        "{}: exon {} has frame {}, expected {}",
+                        self.name,
+                        number + 1,
+                        frame,
+                        expected
+                    ));
+                }
+            }
+            cds += cds_exon_end - cds_exon_start;
+        }
+
+        if !cds.is_multiple_of(3) {
+            problems.push(format!(
+                "{}: CDS length {} is not a multiple of 3",
+                self.name, cds
+            ));
+        }
+
+        problems
+    }
+}
+
+impl FromStr for BedRecord {
+    type Err = ParseError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        BedRecord::parse(line)
+    }
+}
+
+/// Writes the record back as a tab-separated BED12 line, blocks relative to
+/// `tx_start` with trailing commas; an unset itemRgb is written as 0. A
+/// name2 is kept as a 13th column, followed by any further columns as read.
+impl fmt::Display for BedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (mut sizes, mut starts) = (String::new(), String::new());
+        for (start, end) in self.exon_start.iter().zip(&self.exon_end) {
+            sizes.push_str(&format!("{},", end - start));
+            starts.push_str(&format!("{},", start - self.tx_start));
+        }
+
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.chrom,
+            self.tx_start,
+            self.tx_end,
+            self.name,
+            self.score,
+            self.strand,
+            self.cds_start,
+            self.cds_end,
+            self.item_rgb.as_deref().unwrap_or("0"),
+            self.exon_count,
+            sizes,
+            starts
+        )?;
+        if self.name2.is_some() || !self.extra.is_empty() {
+            write!(f, "\t{}", self.name2.as_deref().unwrap_or(""))?;
+        }
+        for field in &self.extra {
+            write!(f, "\t{}", field)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -138,7 +569,155 @@ mod tests {
 
         assert_eq!(
             record,
-            Err("Bed line has less than 12 fields and cannot be parsed into a BedRecord")
+            Err(ParseError::Malformed(
+                "Bed line has less than 12 fields and cannot be parsed into a BedRecord"
+            ))
+        );
+    }
+
+    #[test]
+    fn bed6_record() {
+        let line = "chr1\t14361\t29370\tm64012_190920_173625/4391\t60\t-";
+        let record = BedRecord::parse_bed6(line).unwrap();
+
+        assert_eq!(record.exon_count, 1);
+        assert_eq!(record.exon_start, vec![14361]);
+        assert_eq!(record.exon_end, vec![29370]);
+        assert_eq!(record.cds_start, record.cds_end);
+        assert!(BedRecord::parse(line).is_err());
+    }
+
+    #[test]
+    fn cds_outside_exons() {
+        let line = "chr1\t100\t900\tTX1\t0\t+\t50\t950\t0\t2\t200,300,\t0,500,";
+        let mut record = BedRecord::parse(line).unwrap();
+
+        assert_eq!(
+            record.validate().unwrap_err().to_string(),
+            "TX1: CDS 50-950 is outside the transcript 100-900"
+        );
+
+        record.cds_start = 350;
+        record.cds_end = 850;
+        assert_eq!(
+            record.validate().unwrap_err().to_string(),
+            "TX1: CDS boundary 350 does not fall inside any exon"
+        );
+
+        record.clamp_cds();
+        assert_eq!((record.cds_start, record.cds_end), (600, 850));
+        assert!(record.validate().is_ok());
+    }
+
+    #[test]
+    fn block_count_mismatch() {
+        let line = "chr1\t100\t900\tTX1\t0\t+\t150\t800\t0\t3\t200,300,\t0,500,";
+
+        assert_eq!(
+            BedRecord::parse(line).unwrap_err().to_string(),
+            "TX1: blockCount is 3 but blockSizes lists 2 blocks"
+        );
+    }
+
+    #[test]
+    fn frames_from_cds_length() {
+        // 100 + 4 + 50 coding bases on each strand: GFF3 phases 0, 2, 1
+        let plus = "chr1\t0\t1000\tTX1\t0\t+\t0\t754\t0\t4\t100,4,50,100,\t0,300,700,900,";
+        let minus = "chr1\t0\t1000\tTX2\t0\t-\t246\t1000\t0\t4\t100,50,4,100,\t0,200,500,900,";
+
+        assert_eq!(
+            BedRecord::parse(plus).unwrap().get_frames(),
+            vec![0, 1, 2, -1]
+        );
+        assert_eq!(
+            BedRecord::parse(minus).unwrap().get_frames(),
+            vec![-1, 2, 1, 0]
+        );
+    }
+
+    #[test]
+    fn inconsistent_frames() {
+        let line = "chr1\t0\t1000\tTX1\t0\t+\t0\t749\t0\t4\t100,4,50,100,\t0,300,700,900,";
+        let record = BedRecord::parse(line).unwrap();
+        assert_eq!(record.exon_frames, None);
+        assert!(record.frame_problems().is_empty());
+
+        let stored = |frames: &str| {
+            BedRecord::parse(&format!("{}\tG1\tcmpl\tcmpl\t{}", line, frames)).unwrap()
+        };
+        assert!(stored("0,1,2,-1,").frame_problems().is_empty());
+        assert_eq!(
+            stored("0,2,2,-1,").frame_problems(),
+            ["TX1: exon 2 has frame 2, expected 1"]
+        );
+        // not one frame per exon: ignored
+        assert_eq!(stored("0,2,").exon_frames, None);
+
+        let line = "chr1\t0\t1000\tTX2\t0\t+\t0\t750\t0\t4\t100,4,50,100,\t0,300,700,900,";
+        let record = BedRecord::parse(line).unwrap();
+        assert_eq!(
+            record.frame_problems(),
+            ["TX2: CDS length 154 is not a multiple of 3"]
+        );
+    }
+
+    #[test]
+    fn whitespace_delimited() {
+        let tabs = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0,0,200\t2\t100,200,\t0,200,";
+        let spaces = "chr1 100  500\tTX1 0 + 150 450 0,0,200   2 100,200, 0,200,";
+
+        assert!(BedRecord::parse(spaces).is_err());
+        assert_eq!(BedRecord::parse_whitespace(spaces), BedRecord::parse(tabs));
+        assert_eq!(
+            BedRecord::parse_bed6_whitespace("chr1 100 500 TX2 0 -"),
+            BedRecord::parse_bed6("chr1\t100\t500\tTX2\t0\t-")
+        );
+    }
+
+    #[test]
+    fn name_from_split_field() {
+        let line = "chr1\t100\t500\tGENE1|TX1\t0\t+\t150\t450\t0\t1\t400,\t0,\tTX1.1";
+        let split = NameSource {
+            split: Some('|'),
+            index: 2,
+            ..Default::default()
+        };
+        assert_eq!(split.extract(line, false).unwrap().as_deref(), Some("TX1"));
+
+        let column = NameSource {
+            field: Some(13),
+            ..Default::default()
+        };
+        assert_eq!(
+            column.extract(line, false).unwrap().as_deref(),
+            Some("TX1.1")
+        );
+        assert_eq!(NameSource::default().extract(line, false), Ok(None));
+
+        let missing = NameSource {
+            split: Some('|'),
+            index: 3,
+            ..Default::default()
+        };
+        assert!(missing.extract(line, false).is_err());
+    }
+
+    #[test]
+    fn zero_size_block() {
+        let line = "chr1\t100\t900\tTX1\t0\t+\t150\t800\t0\t3\t200,0,300,\t0,300,500,";
+        assert_eq!(
+            BedRecord::parse(line).unwrap_err().to_string(),
+            "TX1: block 2 has size 0"
+        );
+
+        let line = "chr1\t100\t100\tTX2\t0\t+";
+        assert_eq!(
+            BedRecord::parse_bed6(line),
+            Err(ParseError::EmptyTranscript {
+                name: "TX2".to_string(),
+                tx_start: 100,
+                tx_end: 100
+            })
         );
     }
 
@@ -149,7 +728,61 @@ mod tests {
 
         assert_eq!(
             record,
-            Err("Bed line has less than 12 fields and cannot be parsed into a BedRecord")
+            Err(ParseError::Malformed(
+                "Bed line has less than 12 fields and cannot be parsed into a BedRecord"
+            ))
+        );
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for line in [
+            "chr11\t13934505\t13958243\tENST00000674667\t1000\t-\t13934505\t13958243\t0,0,200\t3\t224,217,228,\t0,1305,23510,",
+            "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,",
+        ] {
+            let record = line.parse::<BedRecord>().unwrap();
+            assert_eq!(record.to_string(), line);
+            assert_eq!(record.to_string().parse::<BedRecord>().unwrap(), record);
+        }
+
+        let bed13 = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,\tGENE1";
+        let record = bed13.parse::<BedRecord>().unwrap();
+        assert_eq!(record.name2.as_deref(), Some("GENE1"));
+        assert_eq!(record.to_string(), bed13);
+
+        let bed16 = format!("{}\tcmpl\tcmpl\t1,0,", bed13);
+        let record = bed16.parse::<BedRecord>().unwrap();
+        assert_eq!(record.exon_frames, Some(vec![1, 0]));
+        assert_eq!(record.to_string(), bed16);
+        assert_eq!(record.to_string().parse::<BedRecord>().unwrap(), record);
+
+        let bed6 = BedRecord::parse_bed6("chr1\t100\t500\tTX1\t0\t-").unwrap();
+        assert_eq!(
+            bed6.to_string(),
+            "chr1\t100\t500\tTX1\t0\t-\t100\t100\t0\t1\t400,\t0,"
+        );
+        assert_eq!(bed6.to_string().parse::<BedRecord>().unwrap(), bed6);
+        assert!("chr1\t100".parse::<BedRecord>().is_err());
+    }
+
+    #[test]
+    fn transcript_and_block_bounds() {
+        let line = "chr1\t500\t500\tTX1\t0\t+\t500\t500\t0\t1\t100,\t0,";
+        assert_eq!(
+            BedRecord::parse(line).unwrap_err().to_string(),
+            "TX1: transcript end 500 is not after its start 500"
+        );
+
+        let line = "chr1\t100\t500\tTX2\t0\t+\t150\t450\t0\t2\t100,300,\t0,200,";
+        assert_eq!(
+            BedRecord::parse(line).unwrap_err().to_string(),
+            "TX2: block 2 lies outside the transcript 100-500"
+        );
+
+        let line = "chr1\t100\t500\tTX3\t0\t+\t150\t450\t0\t70000\t100,\t0,";
+        assert_eq!(
+            BedRecord::parse(line),
+            Err(ParseError::Malformed("blockCount is too large"))
         );
     }
 }
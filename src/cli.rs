@@ -1,12 +1,19 @@
 use clap::{self, ArgAction, Parser};
+use log::LevelFilter;
 use num_cpus;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+use crate::bed::NameSource;
+use crate::config::{
+    check_template, Attribute, Canonical, CategoryMatch, Codec, Feature, Format, LineEnding,
+    PhaseStyle, ProteinIdFrom, SortOrder, StopInCds, TypeNames,
+};
+
 #[derive(Parser, Debug)]
 #[clap(
     name = "bed2gff",
-    version = "0.1.5",
+    version,
     author = "Alejandro Gonzales-Irribarren <jose.gonzalesdezavala1@unmsm.edu.pe>",
     about = "A fast and memory efficient BED to gff converter"
 )]
@@ -14,20 +21,51 @@ pub struct Cli {
     #[clap(
         short = 'b',
         long,
-        help = "Path to BED file",
+        help = "Path to BED file(s), merged into a single output; - reads stdin",
         value_name = "BED",
-        required = true
+        required = true,
+        num_args = 1..
     )]
-    pub bed: PathBuf,
+    pub bed: Vec<PathBuf>,
 
     #[clap(
         short = 'o',
         long,
-        help = "Path to output file",
+        help = "Path to output file, or - for stdout",
         value_name = "OUTPUT",
-        required = true
+        required_unless_present_any = [
+            "coding_out",
+            "output_dir",
+            "report_unmapped_categories",
+            "validate_only"
+        ],
+        conflicts_with_all = ["coding_out", "output_dir"]
+    )]
+    pub output: Option<PathBuf>,
+
+    #[clap(
+        long = "output-dir",
+        help = "Write one <chrom>.gff per chromosome into this directory, instead of -o",
+        value_name = "DIR",
+        conflicts_with = "coding_out"
+    )]
+    pub output_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "coding-out",
+        help = "Path to output file for coding transcripts",
+        value_name = "CODING",
+        requires = "noncoding_out"
+    )]
+    pub coding_out: Option<PathBuf>,
+
+    #[clap(
+        long = "noncoding-out",
+        help = "Path to output file for non-coding transcripts",
+        value_name = "NONCODING",
+        requires = "coding_out"
     )]
-    pub output: PathBuf,
+    pub noncoding_out: Option<PathBuf>,
 
     #[clap(
         short = 't',
@@ -38,6 +76,63 @@ pub struct Cli {
     )]
     pub threads: usize,
 
+    #[arg(
+        long = "append",
+        help = "Append to existing outputs, writing the header only to new ones",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with = "output_dir",
+    )]
+    pub append: bool,
+
+    #[arg(
+        long = "no-header",
+        help = "Write no header: no ##gff-version, #-comments or ##sequence-region lines, only features",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set
+    )]
+    pub no_header: bool,
+
+    #[arg(
+        long = "cds-shared-id",
+        help = "Give all CDS lines of a transcript the same ID=CDS:<name>",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set
+    )]
+    pub cds_shared_id: bool,
+
+    #[arg(
+        long = "utr-as-exon-parts",
+        help = "Split exons holding UTR and CDS into one exon line per part",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set
+    )]
+    pub utr_as_exon_parts: bool,
+
+    #[clap(
+        long = "buffer-size",
+        help = "Capacity in bytes of each output buffer",
+        value_name = "BYTES",
+        default_value_t = crate::utils::BUFFER_SIZE
+    )]
+    pub buffer_size: usize,
+
     #[clap(
         short,
         long = "gz",
@@ -51,10 +146,41 @@ pub struct Cli {
     )]
     pub gz: bool,
 
+    #[arg(
+        long = "bgzip",
+        help = "Compress output with BGZF so it can be indexed with tabix",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with = "gz",
+    )]
+    pub bgzip: bool,
+
+    #[clap(
+        long = "compress-output",
+        help = "Output compression; adds a .gz extension when missing [default: none]",
+        value_name = "CODEC",
+        value_enum,
+        conflicts_with_all = ["gz", "bgzip"]
+    )]
+    pub compress_output: Option<Codec>,
+
+    #[clap(
+        long = "line-ending",
+        help = "Line terminator of the output",
+        value_name = "ENDING",
+        value_enum,
+        default_value_t = LineEnding::Lf
+    )]
+    pub line_ending: LineEnding,
+
     #[arg(
         short,
         long = "no-gene",
-        help = "Flag to disable gene_id feature",
+        help = "Write no gene lines; each transcript is its own gene_id",
         value_name = "FLAG",
         default_missing_value("true"),
         default_value("false"),
@@ -67,12 +193,542 @@ pub struct Cli {
     #[clap(
         short = 'i',
         long,
-        help = "Path to isoforms file",
+        help = "Path to isoforms file; without it each transcript is its own gene",
         value_name = "ISOFORMS",
-        required_unless_present = "no_gene",
         default_value = None,
     )]
     pub isoforms: Option<PathBuf>,
+
+    #[arg(
+        long = "bed6",
+        help = "Accept BED6 lines as single-exon, non-coding transcripts",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub bed6: bool,
+
+    #[arg(
+        long = "whitespace",
+        help = "Split BED fields on any run of spaces or tabs instead of single tabs",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub whitespace: bool,
+
+    #[clap(
+        long = "name-field",
+        help = "1-based BED column holding the transcript name [default: 4]",
+        value_name = "N"
+    )]
+    pub name_field: Option<usize>,
+
+    #[clap(
+        long = "name-split",
+        help = "Split the transcript name on this character and keep the --name-index part",
+        value_name = "CHAR"
+    )]
+    pub name_split: Option<char>,
+
+    #[clap(
+        long = "name-index",
+        help = "1-based part of the split name kept as transcript name",
+        value_name = "K",
+        default_value_t = 1,
+        requires = "name_split"
+    )]
+    pub name_index: usize,
+
+    #[arg(
+        short = 'q',
+        long = "quiet",
+        help = "Only log warnings and errors, without the banner",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub quiet: bool,
+
+    #[clap(
+        long = "log-level",
+        help = "Log verbosity: off, error, warn, info, debug or trace [default: info, warn with --quiet]",
+        value_name = "LEVEL"
+    )]
+    pub log_level: Option<LevelFilter>,
+
+    #[clap(
+        long = "log-file",
+        help = "Append logs to this file instead of stderr",
+        value_name = "PATH"
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[arg(
+        long = "skip-malformed",
+        help = "Skip malformed BED lines with a warning instead of aborting",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub skip_malformed: bool,
+
+    #[arg(
+        long = "strict",
+        help = "Fail on CDS outside exons, duplicate transcript names, unmapped chromosomes, introns over --max-intron or genes across chromosomes/strands",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub strict: bool,
+
+    #[arg(
+        long = "allow-missing-isoforms",
+        help = "Skip transcripts missing from the isoforms file instead of failing",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub allow_missing_isoforms: bool,
+
+    #[clap(
+        long = "iso-gene-col",
+        help = "1-based column of the gene in the isoforms file",
+        value_name = "COL",
+        default_value_t = 1
+    )]
+    pub iso_gene_col: usize,
+
+    #[clap(
+        long = "iso-tx-col",
+        help = "1-based column of the transcript in the isoforms file",
+        value_name = "COL",
+        default_value_t = 2
+    )]
+    pub iso_tx_col: usize,
+
+    #[clap(
+        long = "iso-priority-col",
+        help = "1-based column of a numeric priority in the isoforms file; a transcript listed with several genes keeps the highest",
+        value_name = "COL"
+    )]
+    pub iso_priority_col: Option<usize>,
+
+    #[clap(
+        long = "iso-name-col",
+        help = "1-based column of the gene symbol in the isoforms file, read by --attributes gene_name",
+        value_name = "COL",
+        default_value_t = 3
+    )]
+    pub iso_name_col: usize,
+
+    #[clap(
+        long = "iso-delim",
+        help = "Column delimiter of the isoforms file [default: whitespace]",
+        value_name = "DELIM"
+    )]
+    pub iso_delim: Option<char>,
+
+    #[clap(
+        long = "gene-regex",
+        help = "Regex whose first capture group in the transcript name is its gene, instead of an isoforms file",
+        value_name = "PATTERN",
+        conflicts_with_all = ["isoforms", "no_gene"]
+    )]
+    pub gene_regex: Option<String>,
+
+    #[arg(
+        long = "gene-from-name2",
+        help = "Take each gene from the name2 column (13th) of BED15/bigGenePred input, instead of an isoforms file",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with_all = ["isoforms", "no_gene", "gene_regex"]
+    )]
+    pub gene_from_name2: bool,
+
+    #[clap(
+        short = 'f',
+        long = "format",
+        help = "Output format",
+        value_name = "FORMAT",
+        value_enum,
+        default_value_t = Format::Gff3
+    )]
+    pub format: Format,
+
+    #[clap(
+        long = "gff-version",
+        help = "GFF version to write, 2 or 3; overrides --format",
+        value_name = "VERSION",
+        value_parser = clap::value_parser!(u8).range(2..=3),
+        conflicts_with = "format"
+    )]
+    pub gff_version: Option<u8>,
+
+    #[clap(
+        long = "source",
+        help = "Value of the source column and the #provider header [default: bed2gff]",
+        value_name = "SOURCE"
+    )]
+    pub source: Option<String>,
+
+    #[arg(
+        long = "no-sequence-region",
+        help = "Write no ##sequence-region pragmas",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub no_sequence_region: bool,
+
+    #[arg(
+        long = "no-utr",
+        help = "Write no five_prime_utr/three_prime_utr features",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub no_utr: bool,
+
+    #[clap(
+        long = "features",
+        help = "Comma-separated feature types to write [default: all]",
+        value_name = "TYPES",
+        value_delimiter = ',',
+        value_enum
+    )]
+    pub features: Option<Vec<Feature>>,
+
+    #[clap(
+        long = "id-prefix",
+        help = "Prefix prepended to every generated ID (and Parent) value",
+        value_name = "PREFIX",
+        default_value = ""
+    )]
+    pub id_prefix: String,
+
+    #[arg(
+        long = "so-terms",
+        help = "Write coding transcripts as mRNA in GFF3 output",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub so_terms: bool,
+
+    #[clap(
+        long = "type-names",
+        help = "Naming of the feature type column",
+        value_name = "NAMES",
+        value_enum,
+        default_value_t = TypeNames::So
+    )]
+    pub type_names: TypeNames,
+
+    #[clap(
+        long = "stop-in-cds",
+        help = "Whether CDS features include the stop codon",
+        value_name = "POLICY",
+        value_enum,
+        default_value_t = StopInCds::Exclude
+    )]
+    pub stop_in_cds: StopInCds,
+
+    #[arg(
+        long = "relax-frame",
+        help = "Write start/stop codons at the CDS ends even when they are out of frame",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub relax_frame: bool,
+
+    #[clap(
+        long = "attr-template",
+        help = "Attribute column of transcript features, with {gene}, {tx}, {exon}, {chrom} and {strand} placeholders",
+        value_name = "TEMPLATE"
+    )]
+    pub attr_template: Option<String>,
+
+    #[clap(
+        long = "phase-style",
+        help = "Semantics of column 8 for CDS features; bed-frame is non-standard [default: gff]",
+        value_name = "STYLE",
+        value_enum
+    )]
+    pub phase_style: Option<PhaseStyle>,
+
+    #[clap(
+        long = "default-strand",
+        help = "Strand given to transcripts with strand '.' [default: keep '.']",
+        value_name = "STRAND",
+        value_parser = ["+", "-", "."]
+    )]
+    pub default_strand: Option<String>,
+
+    #[clap(
+        long = "sort",
+        help = "Chromosome order of the output",
+        value_name = "ORDER",
+        value_enum,
+        default_value_t = SortOrder::Natural
+    )]
+    pub sort: SortOrder,
+
+    #[arg(
+        long = "assume-sorted",
+        help = "Skip sorting, warning about records out of --sort order; the input is still read into memory",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set
+    )]
+    pub assume_sorted: bool,
+
+    #[clap(
+        long = "seqid-order",
+        help = "Path to a file listing chromosomes one per line; listed ones are written first, in that order",
+        value_name = "FILE"
+    )]
+    pub seqid_order: Option<PathBuf>,
+
+    #[clap(
+        long = "chrom-sizes",
+        visible_alias = "genome",
+        help = "Path to a chrom.sizes or .fai file used to check transcript bounds",
+        value_name = "SIZES"
+    )]
+    pub chrom_sizes: Option<PathBuf>,
+
+    #[clap(
+        long = "min-exons",
+        help = "Skip transcripts with fewer exons",
+        value_name = "N"
+    )]
+    pub min_exons: Option<u16>,
+
+    #[clap(
+        long = "max-exons",
+        help = "Skip transcripts with more exons",
+        value_name = "N"
+    )]
+    pub max_exons: Option<u16>,
+
+    #[clap(
+        long = "max-intron",
+        help = "Skip transcripts with a longer intron (fail with --strict)",
+        value_name = "N"
+    )]
+    pub max_intron: Option<u32>,
+
+    #[clap(
+        long = "min-cds-length",
+        help = "Skip coding transcripts with a shorter summed CDS; noncoding ones are kept",
+        value_name = "N"
+    )]
+    pub min_cds_length: Option<u32>,
+
+    #[clap(
+        long = "canonical",
+        help = "Keep only the longest isoform of each gene (ties: smallest name)",
+        value_name = "MODE",
+        value_enum
+    )]
+    pub canonical: Option<Canonical>,
+
+    #[clap(
+        long = "max-records",
+        alias = "head",
+        help = "Convert only the first N transcripts in output order; the whole input is still read, filtered and sorted first",
+        value_name = "N"
+    )]
+    pub max_records: Option<usize>,
+
+    #[clap(
+        long = "chrom-map",
+        help = "Path to a two-column (old, new) file renaming chromosomes",
+        value_name = "MAP"
+    )]
+    pub chrom_map: Option<PathBuf>,
+
+    #[clap(
+        long = "rename-transcripts",
+        help = "Path to a two-column file renaming transcripts in the output",
+        value_name = "RENAMES"
+    )]
+    pub rename_transcripts: Option<PathBuf>,
+
+    #[clap(
+        long = "dump-unmapped",
+        help = "Write the names of transcripts missing from the isoforms file to this file",
+        value_name = "FILE",
+        requires = "isoforms"
+    )]
+    pub dump_unmapped: Option<PathBuf>,
+
+    #[clap(
+        long = "incomplete-cds",
+        help = "Write the coding transcripts missing a start or stop codon to this file",
+        value_name = "FILE"
+    )]
+    pub incomplete_cds: Option<PathBuf>,
+
+    #[clap(
+        long = "manifest",
+        help = "Write a JSON summary of the run (inputs, outputs, counts, timing) to this file",
+        value_name = "FILE"
+    )]
+    pub manifest: Option<PathBuf>,
+
+    #[arg(
+        long = "cds-frame-check",
+        help = "Warn about coding transcripts whose stored exonFrames (bigGenePred) disagree with their CDS, or whose CDS is not whole codons",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub cds_frame_check: bool,
+
+    #[arg(
+        long = "validate-only",
+        help = "Check the BED and isoforms files, report problems and exit without writing",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub validate_only: bool,
+
+    #[arg(
+        long = "report-unmapped-categories",
+        help = "Report unmapped transcripts grouped by category and exit",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with = "no_gene",
+        requires = "isoforms",
+    )]
+    pub report_unmapped_categories: bool,
+
+    #[clap(
+        long = "categories",
+        help = "Comma-separated category tags used by --report-unmapped-categories",
+        value_name = "TAGS",
+        value_delimiter = ',',
+        default_value = "PG,PM,L"
+    )]
+    pub categories: Vec<String>,
+
+    #[clap(
+        long = "category-match",
+        help = "Match category tags at the end or the start of transcript names",
+        value_name = "MATCH",
+        value_enum,
+        default_value_t = CategoryMatch::Suffix
+    )]
+    pub category_match: CategoryMatch,
+
+    #[clap(
+        long = "attributes",
+        help = "Comma-separated optional attributes to write",
+        value_name = "ATTRS",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub attributes: Vec<Attribute>,
+
+    #[clap(
+        long = "protein-id-from",
+        help = "Add a protein_id attribute to CDS features",
+        value_name = "FROM",
+        value_enum
+    )]
+    pub protein_id_from: Option<ProteinIdFrom>,
+
+    #[clap(
+        long = "protein-ids",
+        help = "Path to a two-column (transcript, protein) file",
+        value_name = "PROTEINS",
+        required_if_eq("protein_id_from", "file")
+    )]
+    pub protein_ids: Option<PathBuf>,
+
+    #[clap(
+        long = "biotypes",
+        help = "Path to a two-column (transcript, biotype) file adding transcript_biotype and gene_biotype",
+        value_name = "BIOTYPES"
+    )]
+    pub biotypes: Option<PathBuf>,
+
+    #[clap(
+        long = "xref",
+        help = "Path to a (gene or transcript, xrefs[, attribute]) file adding Dbxref or the given attribute",
+        value_name = "XREF"
+    )]
+    pub xref: Option<PathBuf>,
+
+    #[clap(
+        long = "noncoding-biotype",
+        help = "Biotype of transcripts without CDS (e.g. lncRNA); coding ones get protein_coding",
+        value_name = "TYPE"
+    )]
+    pub noncoding_biotype: Option<String>,
+
+    #[arg(
+        long = "validate-gff",
+        help = "Validate the written GFF and exit with an error on problems",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub validate_gff: bool,
 }
 
 #[derive(Debug, Error)]
@@ -88,38 +744,179 @@ impl Cli {
         self.validate_args()
     }
 
+    /// Level from --log-level, else warn with --quiet and info otherwise
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.log_level, self.quiet) {
+            (Some(level), _) => level,
+            (None, true) => LevelFilter::Warn,
+            (None, false) => LevelFilter::Info,
+        }
+    }
+
+    /// Output compression selected by --compress-output, --gz or --bgzip
+    pub fn codec(&self) -> Codec {
+        if let Some(codec) = self.compress_output {
+            codec
+        } else if self.bgzip {
+            Codec::Bgzip
+        } else if self.gz {
+            Codec::Gzip
+        } else {
+            Codec::None
+        }
+    }
+
+    /// Transcript name source selected by --name-field/--name-split/--name-index
+    pub fn name_source(&self) -> NameSource {
+        NameSource {
+            field: self.name_field,
+            split: self.name_split,
+            index: self.name_index,
+        }
+    }
+
+    /// Path written for an output file: with --compress-output, a missing
+    /// `.gz` extension is added
+    pub fn output_path(&self, path: &Path) -> PathBuf {
+        match self.compress_output {
+            Some(codec) => codec.with_extension(path),
+            None => path.to_path_buf(),
+        }
+    }
+
     fn validate_args(&self) -> Result<(), CliError> {
-        validate(&self.bed)?;
+        for bed in &self.bed {
+            if bed.as_os_str() == crate::utils::STDIN {
+                continue;
+            }
+            validate(bed)?;
 
-        match self.bed.extension() {
-            Some(ext) if ext == "bed" => (),
-            _ => {
-                return Err(CliError::InvalidInput(format!(
-                    "file {:?} is not a BED file",
-                    self.bed
-                )))
+            // compressed inputs keep the .bed extension before .gz
+            let plain = match bed.extension() {
+                Some(ext) if ext == "gz" => bed.with_extension(""),
+                _ => bed.clone(),
+            };
+
+            match plain.extension() {
+                Some(ext) if ext == "bed" => (),
+                _ => {
+                    return Err(CliError::InvalidInput(format!(
+                        "file {:?} is not a BED file",
+                        bed
+                    )))
+                }
+            }
+        }
+
+        if let Some(isoforms) = &self.isoforms {
+            if isoforms.as_os_str() != crate::utils::STDIN {
+                validate(isoforms)?;
+            } else if self
+                .bed
+                .iter()
+                .any(|bed| bed.as_os_str() == crate::utils::STDIN)
+            {
+                return Err(CliError::InvalidInput(
+                    "BED and isoforms can't both be read from stdin".to_string(),
+                ));
+            }
+        }
+
+        if let Some(template) = &self.attr_template {
+            check_template(template).map_err(CliError::InvalidInput)?;
+        }
+
+        if let Some(pattern) = &self.gene_regex {
+            match regex::Regex::new(pattern) {
+                Ok(re) if re.captures_len() > 1 => (),
+                Ok(_) => {
+                    return Err(CliError::InvalidInput(format!(
+                        "--gene-regex {:?} has no capture group",
+                        pattern
+                    )))
+                }
+                Err(e) => return Err(CliError::InvalidInput(e.to_string())),
             }
         }
 
-        if !self.no_gene {
-            let isoforms = self.isoforms.as_ref().unwrap();
-            validate(isoforms)?;
+        if self.name_field == Some(0) || self.name_index == 0 {
+            return Err(CliError::InvalidInput(
+                "--name-field and --name-index are 1-based".to_string(),
+            ));
         }
 
-        match self.output.extension() {
-            Some(ext) if ext == "gff" => (),
-            _ => {
+        if self.iso_gene_col == 0
+            || self.iso_tx_col == 0
+            || self.iso_name_col == 0
+            || self.iso_priority_col == Some(0)
+        {
+            return Err(CliError::InvalidInput(
+                "isoforms columns are 1-based".to_string(),
+            ));
+        }
+
+        if let (Some(min), Some(max)) = (self.min_exons, self.max_exons) {
+            if min > max {
                 return Err(CliError::InvalidInput(format!(
-                    "file {:?} is not a GTF file",
-                    self.bed
-                )))
+                    "--min-exons {} is greater than --max-exons {}",
+                    min, max
+                )));
             }
         }
 
+        if let Some(sizes) = &self.chrom_sizes {
+            validate(sizes)?;
+        }
+
+        if let Some(map) = &self.chrom_map {
+            validate(map)?;
+        }
+
+        if let Some(renames) = &self.rename_transcripts {
+            validate(renames)?;
+        }
+
+        if let Some(proteins) = &self.protein_ids {
+            validate(proteins)?;
+        }
+
+        if let Some(biotypes) = &self.biotypes {
+            validate(biotypes)?;
+        }
+
+        if let Some(xref) = &self.xref {
+            validate(xref)?;
+        }
+
+        if let Some(order) = &self.seqid_order {
+            validate(order)?;
+        }
+
+        for output in [&self.output, &self.coding_out, &self.noncoding_out]
+            .into_iter()
+            .flatten()
+        {
+            validate_output(output)?;
+        }
+
         Ok(())
     }
 }
 
+fn validate_output(arg: &PathBuf) -> Result<(), CliError> {
+    if arg.as_os_str() == crate::utils::STDOUT {
+        return Ok(());
+    }
+
+    match arg.extension() {
+        Some(ext) if ext == "gff" || ext == "gff3" || ext == "gtf" => Ok(()),
+        _ => Err(CliError::InvalidInput(format!(
+            "file {:?} is not a GFF/GTF file",
+            arg
+        ))),
+    }
+}
+
 fn validate(arg: &PathBuf) -> Result<(), CliError> {
     if !arg.exists() {
         return Err(CliError::InvalidInput(format!("{:?} does not exist", arg)));
@@ -1,40 +1,66 @@
-use crate::bed::BedRecord;
+use crate::bed::{BedRecord, ParseError};
 use std::cmp::{max, min};
 
-#[derive(Debug, Clone)]
+/// A start or stop codon as 0-based half-open coordinates: `start..end` in
+/// exon `index`, plus `start2..end2` in exon `index2` when an intron splits
+/// it (empty otherwise).
+#[derive(Debug, Clone, Default)]
 pub struct Codon {
     pub start: u32,
     pub end: u32,
     pub index: u32,
     pub start2: u32,
     pub end2: u32,
+    /// exon holding the second part of a codon split by an intron
+    pub index2: u32,
 }
 
 impl Codon {
     pub fn new() -> Codon {
-        Codon {
-            start: 0,
-            end: 0,
-            index: 0,
-            start2: 0,
-            end2: 0,
-        }
+        Codon::default()
     }
 }
 
+/// Codon at the low end of the CDS: the start codon on `+`, the stop codon
+/// on `-`. It is empty (see `codon_complete`) when the CDS does not begin
+/// on a whole codon there; None for noncoding records.
+///
+/// ```
+/// use bed2gff::{codon_complete, first_codon, last_codon, BedRecord};
+///
+/// let record = BedRecord::parse("chr1\t100\t500\tTX1\t0\t+\t150\t451\t0\t2\t100,200,\t0,200,")
+///     .unwrap();
+///
+/// let start = first_codon(&record).unwrap();
+/// let stop = last_codon(&record).unwrap();
+/// assert!(codon_complete(&start) && codon_complete(&stop));
+/// assert_eq!((start.start, start.end), (150, 153));
+/// assert_eq!((stop.start, stop.end), (448, 451));
+/// ```
 pub fn first_codon(record: &BedRecord) -> Option<Codon> {
+    find_first_codon(record, false)
+}
+
+/// The first three coding bases, whatever their frame: `first_codon` for
+/// `--relax-frame`. Not necessarily a real codon.
+pub fn first_boundary_codon(record: &BedRecord) -> Option<Codon> {
+    find_first_codon(record, true)
+}
+
+fn find_first_codon(record: &BedRecord, relax: bool) -> Option<Codon> {
     let exon_frames = record.get_frames();
     record
         .exon_start
         .iter()
         .zip(record.exon_end.iter())
         .enumerate()
-        .find_map(|(mut index, (&start, &end))| {
+        .find_map(|(index, (&start, &end))| {
             let frame = exon_frames.get(index)?;
             let mut codon = Codon::new();
 
+            // UTR-only exon, keep looking
             if *frame < 0 {
-                return Some(codon);
+                return None;
             }
 
             let cds_start = max(start, record.cds_start);
@@ -46,36 +72,45 @@ pub fn first_codon(record: &BedRecord) -> Option<Codon> {
                 (*frame + (cds_end - cds_start) as i16) % 3
             };
 
-            if frame == 0 {
+            if frame == 0 || relax {
                 codon.start = cds_start;
-                codon.end = cds_start + 3;
+                codon.end = min(cds_start + 3, cds_end);
                 codon.index = index as u32;
                 let diff = cds_end - cds_start;
 
-                if diff >= 3 {
-                    Some(codon)
-                } else {
-                    index += 1;
-                    if index >= exon_frames.len() {
-                        Some(codon)
-                    } else {
-                        let need = 3 - diff;
-                        if diff < need {
-                            Some(codon)
-                        } else {
-                            codon.start2 = cds_start;
-                            codon.end2 = cds_start + need;
-                            Some(codon)
-                        }
+                // the rest of the codon opens the next exon
+                if diff < 3 && index + 1 < exon_frames.len() {
+                    let start2 = record.exon_start[index + 1];
+                    let end2 = min(
+                        start2 + 3 - diff,
+                        min(record.exon_end[index + 1], record.cds_end),
+                    );
+                    if start2 < end2 {
+                        codon.start2 = start2;
+                        codon.end2 = end2;
+                        codon.index2 = index as u32 + 1;
                     }
                 }
+                Some(codon)
             } else {
                 Some(Codon::new())
             }
         })
 }
 
+/// Codon at the high end of the CDS: the stop codon on `+`, the start codon
+/// on `-`. Empty and None as for `first_codon`.
 pub fn last_codon(record: &BedRecord) -> Option<Codon> {
+    find_last_codon(record, false)
+}
+
+/// The last three coding bases, whatever their frame: `last_codon` for
+/// `--relax-frame`. Not necessarily a real codon.
+pub fn last_boundary_codon(record: &BedRecord) -> Option<Codon> {
+    find_last_codon(record, true)
+}
+
+fn find_last_codon(record: &BedRecord, relax: bool) -> Option<Codon> {
     let exon_frames = record.get_frames();
     record
         .exon_start
@@ -83,9 +118,15 @@ pub fn last_codon(record: &BedRecord) -> Option<Codon> {
         .zip(record.exon_end.iter())
         .enumerate()
         .rev() // Reverse the iterator to start from the last exon
-        .find_map(|(mut index, (&start, &end))| {
+        .find_map(|(index, (&start, &end))| {
             let mut codon = Codon::new();
             let frame = exon_frames.get(index)?;
+
+            // UTR-only exon, keep looking
+            if *frame < 0 {
+                return None;
+            }
+
             let cds_start = max(start, record.cds_start);
             let cds_end = min(end, record.cds_end);
 
@@ -95,35 +136,127 @@ pub fn last_codon(record: &BedRecord) -> Option<Codon> {
                 *frame
             };
 
-            if frame == 0 {
+            if frame == 0 || relax {
                 codon.start = max(cds_start, cds_end - 3); // Find the last 3 bases of the CDS
                 codon.end = cds_end;
                 codon.index = index as u32;
                 let diff = cds_end - cds_start;
 
-                if diff >= 3 {
-                    Some(codon)
-                } else {
-                    index += 1;
-                    if index >= exon_frames.len() {
-                        Some(codon)
-                    } else {
-                        let need = 3 - diff;
-                        if diff < need {
-                            Some(codon)
-                        } else {
-                            codon.start2 = cds_start;
-                            codon.end2 = cds_start + need;
-                            Some(codon)
-                        }
+                // the rest of the codon closes the previous exon
+                if diff < 3 && index > 0 {
+                    let end2 = record.exon_end[index - 1];
+                    let start2 = max(
+                        end2.saturating_sub(3 - diff),
+                        max(record.exon_start[index - 1], record.cds_start),
+                    );
+                    if start2 < end2 {
+                        codon.start2 = start2;
+                        codon.end2 = end2;
+                        codon.index2 = index as u32 - 1;
                     }
                 }
+                Some(codon)
             } else {
                 Some(Codon::new())
             }
         })
 }
 
+/// Whether both parts of the codon add up to three bases
 pub fn codon_complete(codon: &Codon) -> bool {
     ((codon.end - codon.start) + (codon.end2 - codon.start2)) == 3
 }
+
+/// Moves the base at `pos` by `dist` exonic bases, skipping introns. Exons
+/// are half-open, so an exon end belongs to the following intron (or to an
+/// adjacent exon starting there). Fails when `pos` is not inside an exon or
+/// the move runs past the first or last exon.
+pub fn move_pos(record: &BedRecord, pos: u32, dist: i32) -> Result<u32, ParseError> {
+    let mut pos = pos;
+
+    let mut exon_index = record
+        .exon_start
+        .iter()
+        .zip(record.exon_end.iter())
+        .position(|(start, end)| pos >= *start && pos < *end)
+        .ok_or_else(|| ParseError::NotInExons {
+            name: record.name.clone(),
+            pos,
+        })? as i16;
+
+    let mut steps = dist.abs();
+    let direction = if dist >= 0 { 1 } else { -1 };
+
+    while steps > 0 {
+        let (exon_start, exon_end) = (
+            record.exon_start[exon_index as usize],
+            record.exon_end[exon_index as usize],
+        );
+
+        if (direction > 0 && pos + 1 < exon_end) || (direction < 0 && pos > exon_start) {
+            pos = pos.wrapping_add_signed(direction);
+            steps -= 1;
+        } else if direction > 0 {
+            exon_index += 1;
+            if (exon_index as usize) < record.exon_start.len() {
+                pos = record.exon_start[exon_index as usize];
+                steps -= 1;
+            } else {
+                break;
+            }
+        } else {
+            exon_index -= 1;
+            if exon_index >= 0 {
+                pos = record.exon_end[exon_index as usize] - 1;
+                steps -= 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    if steps > 0 {
+        return Err(ParseError::CannotMove {
+            name: record.name.clone(),
+            pos,
+            dist,
+        });
+    }
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_past_last_exon() {
+        let line = "chr1\t100\t200\tTX1\t0\t+\t100\t200\t0\t2\t2,50,\t0,50,";
+        let record = BedRecord::parse(line).unwrap();
+
+        assert_eq!(move_pos(&record, 101, 3), Ok(152));
+        assert_eq!(move_pos(&record, 150, -2), Ok(100));
+        assert_eq!(
+            move_pos(&record, 199, 5).unwrap_err().to_string(),
+            "TX1: can't move 199 by 5"
+        );
+        assert_eq!(
+            move_pos(&record, 120, 3).unwrap_err().to_string(),
+            "TX1: position 120 is not inside any exon"
+        );
+    }
+
+    #[test]
+    fn adjacent_exons() {
+        // exon 1 ends where exon 2 begins; the stop codon takes 198, 199, 200
+        let line = "chr1\t100\t300\tTX1\t0\t+\t150\t201\t0\t2\t100,100,\t0,100,";
+        let record = BedRecord::parse(line).unwrap();
+
+        assert_eq!(move_pos(&record, 199, 1), Ok(200));
+        assert_eq!(move_pos(&record, 200, -3), Ok(197));
+
+        let codon = last_codon(&record).unwrap();
+        assert_eq!((codon.start, codon.end, codon.index), (200, 201, 1));
+        assert_eq!((codon.start2, codon.end2, codon.index2), (198, 200, 0));
+    }
+}
@@ -0,0 +1,538 @@
+use clap::ValueEnum;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::bed::BedRecord;
+use crate::cli::{Cli, CliError};
+use crate::gff::SOURCE;
+use crate::utils::{
+    get_chrom_sizes, parallel_hash, read_file, seqid_ranks, xref_map, CrlfWriter, STDOUT,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub format: Format,
+    pub phase_style: PhaseStyle,
+    pub protein_id_from: Option<ProteinIdFrom>,
+    pub protein_ids: HashMap<String, String>,
+    pub source: Option<String>,
+    /// gene -> symbol, written as gene_name
+    pub gene_names: HashMap<String, String>,
+    pub so_terms: bool,
+    /// prepended to every ID and Parent value
+    pub id_prefix: String,
+    /// feature types to write, all when None
+    pub features: Option<Vec<Feature>>,
+    pub no_sequence_region: bool,
+    /// chromosome lengths from `--chrom-sizes`/`--genome`
+    pub chrom_sizes: HashMap<String, u32>,
+    pub sort: SortOrder,
+    /// chromosome -> rank from `--seqid-order`, ahead of `sort`
+    pub seqid_order: HashMap<String, usize>,
+    pub no_utr: bool,
+    pub type_names: TypeNames,
+    pub stop_in_cds: StopInCds,
+    /// codons at the CDS ends whatever their frame
+    pub relax_frame: bool,
+    pub line_ending: LineEnding,
+    /// --no-header: no pragma, comment or ##sequence-region lines at all
+    pub no_header: bool,
+    pub cds_shared_id: bool,
+    /// `--no-gene`: no gene lines, so transcripts get no Parent
+    pub no_gene: bool,
+    pub utr_as_exon_parts: bool,
+    /// `--attr-template` replacing the attribute column of transcript features
+    pub attr_template: Option<String>,
+    /// transcript -> biotype from `--biotypes`
+    pub biotypes: Option<HashMap<String, String>>,
+    /// `--noncoding-biotype` of transcripts without CDS
+    pub noncoding_biotype: Option<String>,
+    /// gene or transcript -> (attribute, values) from `--xref`
+    pub xrefs: HashMap<String, Vec<(String, Vec<String>)>>,
+}
+
+impl TryFrom<&Cli> for Config {
+    type Error = CliError;
+
+    fn try_from(args: &Cli) -> Result<Self, Self::Error> {
+        let protein_ids = match &args.protein_ids {
+            Some(path) => parallel_hash(&read_file(path, "protein IDs file")?),
+            None => HashMap::new(),
+        };
+
+        let biotypes = match &args.biotypes {
+            Some(path) => Some(parallel_hash(&read_file(path, "biotypes file")?)),
+            None => None,
+        };
+
+        let xrefs = match &args.xref {
+            Some(path) => xref_map(&read_file(path, "xref file")?),
+            None => HashMap::new(),
+        };
+
+        let chrom_sizes = match &args.chrom_sizes {
+            Some(path) => get_chrom_sizes(&read_file(path, "chromosome sizes file")?),
+            None => HashMap::new(),
+        };
+
+        let seqid_order = match &args.seqid_order {
+            Some(path) => seqid_ranks(&read_file(path, "seqid order file")?),
+            None => HashMap::new(),
+        };
+
+        let format = match args.gff_version {
+            Some(2) => Format::Gff2,
+            Some(_) => Format::Gff3,
+            None => args.format,
+        };
+
+        Ok(Config {
+            format,
+            phase_style: args.phase_style.unwrap_or_default(),
+            protein_id_from: args.protein_id_from,
+            protein_ids,
+            source: args.source.clone(),
+            // filled by the caller, from the same pass as the isoforms map
+            gene_names: HashMap::new(),
+            so_terms: args.so_terms,
+            id_prefix: args.id_prefix.clone(),
+            features: args.features.clone(),
+            no_sequence_region: args.no_sequence_region,
+            chrom_sizes,
+            sort: args.sort,
+            seqid_order,
+            no_utr: args.no_utr,
+            type_names: args.type_names,
+            stop_in_cds: args.stop_in_cds,
+            relax_frame: args.relax_frame,
+            line_ending: args.line_ending,
+            no_header: args.no_header,
+            cds_shared_id: args.cds_shared_id,
+            no_gene: args.no_gene,
+            utr_as_exon_parts: args.utr_as_exon_parts,
+            attr_template: args.attr_template.clone(),
+            biotypes,
+            noncoding_biotype: args.noncoding_biotype.clone(),
+            xrefs,
+        })
+    }
+}
+
+impl Config {
+    /// Value of the source column, `bed2gff` unless overridden
+    pub fn source(&self) -> &str {
+        self.source.as_deref().unwrap_or(SOURCE)
+    }
+
+    /// Feature type of a transcript line: with `--so-terms`, GFF3 coding
+    /// transcripts are written as mRNA
+    pub fn transcript_type(&self, record: &BedRecord) -> &'static str {
+        if self.so_terms && self.format == Format::Gff3 && record.cds_start < record.cds_end {
+            "mRNA"
+        } else {
+            "transcript"
+        }
+    }
+
+    /// Value of an ID (or of a Parent pointing at one) under `--id-prefix`
+    pub fn id(&self, id: &str) -> String {
+        format!("{}{}", self.id_prefix, id)
+    }
+
+    /// ID of a gene line, or of a transcript Parent pointing at one. A gene
+    /// named like a transcript, e.g. each transcript as its own gene, gets
+    /// a `gene:` prefix so the two IDs stay unique
+    pub fn gene_id(&self, gene: &str, is_transcript: bool) -> String {
+        match is_transcript {
+            true => self.id(&format!("gene:{}", gene)),
+            false => self.id(gene),
+        }
+    }
+
+    /// Whether lines of this feature type are written
+    pub fn emits(&self, feature: Feature) -> bool {
+        self.features
+            .as_ref()
+            .is_none_or(|features| features.contains(&feature))
+    }
+
+    /// Biotype of a transcript under `--biotypes` or `--noncoding-biotype`:
+    /// its mapped value, else protein_coding for coding transcripts and the
+    /// `--noncoding-biotype`, if any, for the rest
+    pub fn biotype(&self, record: &BedRecord) -> Option<&str> {
+        if self.biotypes.is_none() && self.noncoding_biotype.is_none() {
+            return None;
+        }
+        match self.biotypes.as_ref().and_then(|b| b.get(&record.name)) {
+            Some(biotype) => Some(biotype),
+            None if record.cds_start < record.cds_end => Some("protein_coding"),
+            None => self.noncoding_biotype.as_deref(),
+        }
+    }
+
+    /// `--xref` attributes of a gene or transcript, values comma-joined
+    pub fn xrefs(&self, key: &str) -> Vec<(&str, String)> {
+        self.xrefs.get(key).map_or_else(Vec::new, |attrs| {
+            attrs
+                .iter()
+                .map(|(attr, values)| (attr.as_str(), values.join(",")))
+                .collect()
+        })
+    }
+
+    /// protein_id of the CDS features of a transcript, if any
+    pub fn protein_id(&self, transcript: &str) -> Option<String> {
+        match self.protein_id_from? {
+            ProteinIdFrom::Transcript => Some(format!("{}.p", transcript)),
+            ProteinIdFrom::File => self.protein_ids.get(transcript).cloned(),
+        }
+    }
+}
+
+/// Output compression codec.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Codec {
+    #[default]
+    None,
+    Gzip,
+    /// blocked gzip, readable by gzip and indexable with tabix
+    Bgzip,
+}
+
+impl Codec {
+    /// `path` with a `.gz` suffix appended when compressing, unless it
+    /// already has one or is stdout
+    pub fn with_extension(self, path: &Path) -> PathBuf {
+        let name = path.as_os_str();
+        if self == Codec::None || name == STDOUT || path.extension().is_some_and(|e| e == "gz") {
+            return path.to_path_buf();
+        }
+
+        let mut name = name.to_os_string();
+        name.push(".gz");
+        PathBuf::from(name)
+    }
+}
+
+/// Optional attributes enabled with `--attributes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Attribute {
+    /// gene symbol from the third column of the isoforms file
+    #[value(name = "gene_name")]
+    GeneName,
+}
+
+/// Feature types selectable with `--features`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Feature {
+    Gene,
+    /// transcript or mRNA
+    Transcript,
+    Exon,
+    Cds,
+    /// five_prime_utr and three_prime_utr
+    Utr,
+    /// start_codon and stop_codon
+    Codon,
+}
+
+impl Feature {
+    /// Selectable type of a written feature type
+    pub fn of(feature: &str) -> Option<Feature> {
+        match feature {
+            "gene" => Some(Feature::Gene),
+            "transcript" | "mRNA" => Some(Feature::Transcript),
+            "exon" => Some(Feature::Exon),
+            "CDS" => Some(Feature::Cds),
+            "five_prime_utr" | "three_prime_utr" => Some(Feature::Utr),
+            "start_codon" | "stop_codon" => Some(Feature::Codon),
+            _ => None,
+        }
+    }
+}
+
+/// Line terminator of the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LineEnding {
+    /// \n
+    #[default]
+    Lf,
+    /// \r\n, for Windows tools
+    Crlf,
+}
+
+impl LineEnding {
+    /// `writer`, ending every line it writes with this terminator
+    pub fn wrap<'a>(self, writer: Box<dyn Write + Send + 'a>) -> Box<dyn Write + Send + 'a> {
+        match self {
+            LineEnding::Lf => writer,
+            LineEnding::Crlf => Box::new(CrlfWriter::new(writer)),
+        }
+    }
+}
+
+/// Isoform kept per gene by `--canonical`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Canonical {
+    /// Isoform with the most coding bases
+    LongestCds,
+    /// Isoform with the most exonic bases
+    LongestTx,
+}
+
+/// Whether CDS features cover the stop codon.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum StopInCds {
+    /// CDS ends at the last base before the stop codon (GTF2.2)
+    #[default]
+    Exclude,
+    /// CDS ends at thickEnd (thickStart on `-`), stop codon included
+    Include,
+}
+
+/// Placeholders of `--attr-template`
+pub const TEMPLATE_KEYS: [&str; 5] = ["gene", "tx", "exon", "chrom", "strand"];
+
+/// Checks that every `{...}` of an `--attr-template` is a known placeholder
+/// and that braces are balanced.
+pub fn check_template(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest[open..].starts_with('}') {
+            return Err(format!("unmatched '}}' in {:?}", template));
+        }
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in {:?}", template))?
+            + open;
+        let key = &rest[open + 1..close];
+        if !TEMPLATE_KEYS.contains(&key) {
+            return Err(format!(
+                "unknown placeholder {{{}}} (expected one of {})",
+                key,
+                TEMPLATE_KEYS.map(|key| format!("{{{}}}", key)).join(", ")
+            ));
+        }
+        rest = &rest[close + 1..];
+    }
+    Ok(())
+}
+
+/// Fills a checked `--attr-template` with `values`. A `;`-separated part
+/// with a placeholder lacking a value for this feature (`{exon}` on
+/// transcript and UTR lines) is left out.
+pub fn render_template(template: &str, values: &[(&str, Option<&str>)]) -> String {
+    let fill = |part: &str| -> Option<String> {
+        let mut filled = String::new();
+        let mut rest = part;
+        while let Some(open) = rest.find('{') {
+            let close = rest[open..].find('}')? + open;
+            let key = &rest[open + 1..close];
+            filled.push_str(&rest[..open]);
+            filled.push_str(values.iter().find(|(k, _)| *k == key)?.1?);
+            rest = &rest[close + 1..];
+        }
+        filled.push_str(rest);
+        Some(filled)
+    };
+
+    template
+        .split(';')
+        .filter_map(fill)
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Written type of each feature under each naming convention, by column:
+/// SO terms (the names used internally), lowercase, abbreviated.
+const FEATURE_TYPES: [[&str; 3]; 9] = [
+    ["gene", "gene", "gene"],
+    ["transcript", "transcript", "transcript"],
+    ["mRNA", "mrna", "mRNA"],
+    ["exon", "exon", "exon"],
+    ["CDS", "cds", "CDS"],
+    ["five_prime_utr", "five_prime_utr", "5UTR"],
+    ["three_prime_utr", "three_prime_utr", "3UTR"],
+    ["start_codon", "start_codon", "start_codon"],
+    ["stop_codon", "stop_codon", "stop_codon"],
+];
+
+/// Naming convention of the feature type column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TypeNames {
+    /// CDS, five_prime_utr, three_prime_utr
+    #[default]
+    So,
+    /// cds, five_prime_utr, three_prime_utr
+    Lowercase,
+    /// CDS, 5UTR, 3UTR
+    Abbreviated,
+}
+
+impl TypeNames {
+    /// Name of an internal (SO) feature type under this convention; unknown
+    /// types are kept as they are
+    pub fn name(self, feature: &str) -> &str {
+        FEATURE_TYPES
+            .iter()
+            .find(|names| names[0] == feature)
+            .map_or(feature, |names| names[self as usize])
+    }
+}
+
+/// Source of the `protein_id` attribute written on CDS features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProteinIdFrom {
+    /// `<transcript>.p`
+    Transcript,
+    /// transcript-to-protein mapping given with `--protein-ids`
+    File,
+}
+
+/// Output flavor: GFF3 (`key=value` attributes with ID/Parent links),
+/// GTF2.2 (`key "value";` attributes without ID/Parent and no gene lines)
+/// or GFF2 (GTF-style attributes, gene lines kept).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Gff3,
+    Gtf,
+    Gff2,
+}
+
+impl Format {
+    /// File extension of outputs named by bed2gff
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Gff3 | Format::Gff2 => "gff",
+            Format::Gtf => "gtf",
+        }
+    }
+
+    /// `##gff-version` pragma opening the output, if the format has one
+    pub fn pragma(&self) -> Option<&'static str> {
+        match self {
+            Format::Gff3 => Some("##gff-version 3"),
+            Format::Gff2 => Some("##gff-version 2"),
+            Format::Gtf => None,
+        }
+    }
+
+    /// Serializes attributes in the syntax of this format
+    pub fn attributes(&self, attrs: &[(&str, String)]) -> String {
+        match self {
+            Format::Gff3 => attrs
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+                .join(";"),
+            Format::Gtf | Format::Gff2 => attrs
+                .iter()
+                .filter(|(key, _)| *key != "ID" && *key != "Parent")
+                .map(|(key, value)| format!("{} \"{}\";", key, value))
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+/// Semantics used to fill column 8 of CDS features.
+///
+/// BED frames (as computed by `BedRecord::get_frames`) count how many bases
+/// of the current codon were already consumed by upstream CDS exons. GFF3
+/// phase and GTF2.2 frame both mean the bases to skip before the first
+/// whole codon, so every format uses `gff`; `bed-frame` writes the raw BED
+/// frame, which is not standard in any of them:
+///
+/// | BED frame | `gff` (GFF3 phase, GTF frame) | `bed-frame` |
+/// |-----------|-------------------------------|-------------|
+/// | 0         | 0                             | 0           |
+/// | 1         | 2                             | 1           |
+/// | 2         | 1                             | 2           |
+/// | -1 (none) | .                             | .           |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PhaseStyle {
+    /// GFF3 phase / GTF2.2 frame: bases to remove from the feature start to
+    /// reach the next codon
+    #[default]
+    Gff,
+    /// Non-standard: bases of the current codon already consumed upstream
+    BedFrame,
+}
+
+impl PhaseStyle {
+    /// Column 8 value for a signed BED frame; -1 (or any value outside 0..=2)
+    /// means no frame
+    pub fn column(&self, frame: i16) -> &'static str {
+        match (self, frame) {
+            (_, 0) => "0",
+            (PhaseStyle::Gff, 1) => "2",
+            (PhaseStyle::Gff, 2) => "1",
+            (PhaseStyle::BedFrame, 1) => "1",
+            (PhaseStyle::BedFrame, 2) => "2",
+            _ => ".",
+        }
+    }
+}
+
+/// Chromosome order of the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// chr2 before chr10
+    #[default]
+    Natural,
+    /// chr10 before chr2
+    Lexicographic,
+    /// input order, transcripts unsorted
+    None,
+}
+
+/// Where category tags are looked for in unmapped transcript names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CategoryMatch {
+    #[default]
+    Suffix,
+    Prefix,
+}
+
+impl CategoryMatch {
+    pub fn matches(&self, name: &str, category: &str) -> bool {
+        match self {
+            CategoryMatch::Suffix => name.ends_with(category),
+            CategoryMatch::Prefix => name.starts_with(category),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phase_column() {
+        let frames = [-1, 0, 1, 2];
+        assert_eq!(
+            frames.map(|frame| PhaseStyle::Gff.column(frame)),
+            [".", "0", "2", "1"]
+        );
+        assert_eq!(
+            frames.map(|frame| PhaseStyle::BedFrame.column(frame)),
+            [".", "0", "1", "2"]
+        );
+    }
+
+    #[test]
+    fn codec_extensions() {
+        let path = Path::new("out.gff");
+        assert_eq!(Codec::None.with_extension(path), path);
+        assert_eq!(Codec::Gzip.with_extension(path), Path::new("out.gff.gz"));
+        assert_eq!(Codec::Bgzip.with_extension(path), Path::new("out.gff.gz"));
+        assert_eq!(
+            Codec::Gzip.with_extension(Path::new("out.gff.gz")),
+            Path::new("out.gff.gz")
+        );
+        assert_eq!(Codec::Gzip.with_extension(Path::new("-")), Path::new("-"));
+    }
+}
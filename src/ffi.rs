@@ -0,0 +1,155 @@
+use crate::config::Config;
+use crate::gff::bed2gff_stream;
+use crate::utils::{open_input, path_error};
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{c_char, CStr, CString};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::panic;
+use std::path::PathBuf;
+use std::ptr;
+
+/// Conversion succeeded
+pub const BED2GFF_OK: i32 = 0;
+/// A path was null or not valid UTF-8
+pub const BED2GFF_INVALID_ARGUMENT: i32 = 1;
+/// Reading, parsing or writing failed
+pub const BED2GFF_ERROR: i32 = 2;
+/// The conversion panicked
+pub const BED2GFF_PANIC: i32 = 3;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+unsafe fn path_arg(ptr: *const c_char, name: &str) -> Result<PathBuf, String> {
+    if ptr.is_null() {
+        return Err(format!("{} path is null", name));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map(PathBuf::from)
+        .map_err(|_| format!("{} path is not valid UTF-8", name))
+}
+
+fn convert(bed: &PathBuf, isoforms: Option<&PathBuf>, out: &PathBuf) -> Result<(), Box<dyn Error>> {
+    let bed = open_input(bed).map_err(|e| path_error(e, "open BED file", bed))?;
+    let isoforms = isoforms
+        .map(|path| open_input(path).map_err(|e| path_error(e, "open isoforms file", path)))
+        .transpose()?;
+    let file = File::create(out).map_err(|e| path_error(e, "create output file", out))?;
+    let mut writer = BufWriter::new(file);
+    bed2gff_stream(bed, isoforms, &mut writer, &Config::default())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Converts the BED file at `bed` into GFF3 at `out` with the default
+/// config. `iso` is an isoforms file, or null to make each transcript its
+/// own gene. Inputs may be gzipped. Returns `BED2GFF_OK` (0) or a nonzero
+/// code whose message `bed2gff_last_error` returns.
+///
+/// # Safety
+///
+/// `bed` and `out` must be valid NUL-terminated strings; `iso` must be one
+/// or null.
+#[no_mangle]
+pub unsafe extern "C" fn bed2gff_convert(
+    bed: *const c_char,
+    iso: *const c_char,
+    out: *const c_char,
+) -> i32 {
+    let paths = (|| {
+        let isoforms = match iso.is_null() {
+            true => None,
+            false => Some(path_arg(iso, "isoforms")?),
+        };
+        Ok::<_, String>((path_arg(bed, "BED")?, isoforms, path_arg(out, "output")?))
+    })();
+    let (bed, isoforms, out) = match paths {
+        Ok(paths) => paths,
+        Err(e) => {
+            set_last_error(e);
+            return BED2GFF_INVALID_ARGUMENT;
+        }
+    };
+
+    match panic::catch_unwind(|| convert(&bed, isoforms.as_ref(), &out)) {
+        Ok(Ok(())) => {
+            LAST_ERROR.with(|last| *last.borrow_mut() = None);
+            BED2GFF_OK
+        }
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            BED2GFF_ERROR
+        }
+        Err(_) => {
+            set_last_error("conversion panicked".to_string());
+            BED2GFF_PANIC
+        }
+    }
+}
+
+/// Message of the last failed `bed2gff_convert` on this thread, or null if
+/// it succeeded. The string is owned by the library and valid until the
+/// next `bed2gff_convert` call on the same thread.
+#[no_mangle]
+pub extern "C" fn bed2gff_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c_path(path: &std::path::Path) -> CString {
+        CString::new(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn convert_through_c_abi() {
+        let dir = std::env::temp_dir().join("bed2gff_convert_through_c_abi");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (bed, iso, out) = (dir.join("a.bed"), dir.join("iso.txt"), dir.join("a.gff"));
+        std::fs::write(
+            &bed,
+            "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,\n",
+        )
+        .unwrap();
+        std::fs::write(&iso, "GENE1\tTX1\n").unwrap();
+
+        let (bed, iso, out_c) = (c_path(&bed), c_path(&iso), c_path(&out));
+        let status = unsafe { bed2gff_convert(bed.as_ptr(), iso.as_ptr(), out_c.as_ptr()) };
+        assert_eq!(status, BED2GFF_OK);
+        assert!(bed2gff_last_error().is_null());
+        let gff = std::fs::read_to_string(&out).unwrap();
+        assert!(gff.contains("\tgene\t101\t500\t.\t+\t.\tID=GENE1;gene_id=GENE1\n"));
+
+        let missing = c_path(&dir.join("missing.bed"));
+        let status = unsafe { bed2gff_convert(missing.as_ptr(), ptr::null(), out_c.as_ptr()) };
+        assert_eq!(status, BED2GFF_ERROR);
+        let message = unsafe { CStr::from_ptr(bed2gff_last_error()) };
+        assert!(message.to_str().unwrap().starts_with(&format!(
+            "could not open BED file '{}': ",
+            dir.join("missing.bed").display()
+        )));
+
+        let status = unsafe { bed2gff_convert(ptr::null(), ptr::null(), out_c.as_ptr()) };
+        assert_eq!(status, BED2GFF_INVALID_ARGUMENT);
+        let message = unsafe { CStr::from_ptr(bed2gff_last_error()) };
+        assert_eq!(message.to_str().unwrap(), "BED path is null");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1170 @@
+use crate::bed::BedRecord;
+use crate::config::{Codec, Config, SortOrder};
+use crate::lines::to_gff;
+use crate::utils::{
+    comments, create_writer, dedup_names, gene_lines, get_isoforms, identity_isoforms,
+    parallel_parse, path_error, split_genes,
+};
+
+use natord::compare;
+
+use rayon::prelude::*;
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc;
+
+/// Default value of the source column
+pub const SOURCE: &str = "bed2gff";
+
+/// Records converted in parallel before a chunk is handed to the writer.
+const CHUNK_SIZE: usize = 10_000;
+/// Chunks allowed in flight between the converters and the writer.
+const CHANNEL_BOUND: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GffRecord {
+    pub seqid: String,
+    pub source: String,
+    pub feature: String,
+    pub start: u32,
+    pub end: u32,
+    pub score: String,
+    pub strand: String,
+    pub phase: String,
+    pub attributes: String,
+}
+
+/// Counts of the features written by a conversion.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConversionStats {
+    pub genes: usize,
+    pub transcripts: usize,
+    pub exons: usize,
+    pub cds: usize,
+    pub utr: usize,
+    /// records that could not be converted
+    pub skipped: usize,
+}
+
+impl ConversionStats {
+    fn count(&mut self, line: &GffRecord) {
+        match line.feature.as_str() {
+            "gene" => self.genes += 1,
+            "transcript" | "mRNA" => self.transcripts += 1,
+            "exon" => self.exons += 1,
+            "CDS" => self.cds += 1,
+            "five_prime_utr" | "three_prime_utr" => self.utr += 1,
+            _ => (),
+        }
+    }
+}
+
+impl AddAssign for ConversionStats {
+    fn add_assign(&mut self, other: Self) {
+        self.genes += other.genes;
+        self.transcripts += other.transcripts;
+        self.exons += other.exons;
+        self.cds += other.cds;
+        self.utr += other.utr;
+        self.skipped += other.skipped;
+    }
+}
+
+impl fmt::Display for ConversionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} genes, {} transcripts, {} exons, {} CDS, {} UTRs ({} records skipped)",
+            self.genes, self.transcripts, self.exons, self.cds, self.utr, self.skipped
+        )
+    }
+}
+
+impl GffRecord {
+    /// Value of the first `key=value` attribute named `key`
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .split(';')
+            .find_map(|kv| kv.strip_prefix(key)?.strip_prefix('='))
+    }
+}
+
+impl FromStr for GffRecord {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 9 {
+            return Err(format!("expected 9 columns, found {}", fields.len()));
+        }
+
+        let coord = |field: &str| {
+            field
+                .parse::<u32>()
+                .map_err(|_| format!("invalid coordinate {:?}", field))
+        };
+
+        Ok(GffRecord {
+            seqid: fields[0].to_string(),
+            source: fields[1].to_string(),
+            feature: fields[2].to_string(),
+            start: coord(fields[3])?,
+            end: coord(fields[4])?,
+            score: fields[5].to_string(),
+            strand: fields[6].to_string(),
+            phase: fields[7].to_string(),
+            attributes: fields[8].to_string(),
+        })
+    }
+}
+
+impl fmt::Display for GffRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.seqid,
+            self.source,
+            self.feature,
+            self.start,
+            self.end,
+            self.score,
+            self.strand,
+            self.phase,
+            self.attributes
+        )
+    }
+}
+
+/// Sorts records by chromosome (in `order`), start, end and name, which is
+/// the order transcripts are written in; `SortOrder::None` keeps the input
+/// order. Chromosomes ranked in `seqids` come first, in rank order, and the
+/// rest follow in `order`. Records are parsed once on input and sorted in
+/// place, so conversion never goes back to the BED text.
+pub fn sort_records(records: &mut [BedRecord], order: SortOrder, seqids: &HashMap<String, usize>) {
+    if let Some(cmp) = record_order(order, seqids) {
+        records.par_sort_by(cmp);
+    }
+}
+
+/// Positions of the records that `sort_records` would move before the
+/// record preceding them; empty if the input is already in that order.
+/// Lets `--assume-sorted` check its input without sorting it.
+pub fn unsorted_records(
+    records: &[BedRecord],
+    order: SortOrder,
+    seqids: &HashMap<String, usize>,
+) -> Vec<usize> {
+    match record_order(order, seqids) {
+        Some(cmp) => records
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| cmp(&pair[0], &pair[1]) == Ordering::Greater)
+            .map(|(idx, _)| idx + 1)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Comparator of `sort_records`, None for `SortOrder::None`
+fn record_order(
+    order: SortOrder,
+    seqids: &HashMap<String, usize>,
+) -> Option<impl Fn(&BedRecord, &BedRecord) -> Ordering + Sync + '_> {
+    let chroms = match order {
+        SortOrder::Natural => compare,
+        SortOrder::Lexicographic => |a: &str, b: &str| a.cmp(b),
+        SortOrder::None => return None,
+    };
+    let rank = |chrom: &str| seqids.get(chrom).copied().unwrap_or(usize::MAX);
+
+    Some(move |a: &BedRecord, b: &BedRecord| {
+        rank(&a.chrom)
+            .cmp(&rank(&b.chrom))
+            .then_with(|| chroms(&a.chrom, &b.chrom))
+            .then(a.tx_start.cmp(&b.tx_start))
+            .then(a.tx_end.cmp(&b.tx_end))
+            .then(a.name.cmp(&b.name))
+    })
+}
+
+/// Groups sorted records into gene blocks: all transcripts of a gene on one
+/// chromosome, in their sorted order, with blocks ordered by their first
+/// transcript. Transcripts of genes interleaved by coordinate thus stay
+/// together under their gene line. Records missing from `isoforms` are
+/// blocks of their own.
+pub fn gene_blocks<'a>(
+    records: &'a [BedRecord],
+    isoforms: &HashMap<String, String>,
+) -> Vec<&'a BedRecord> {
+    let mut blocks: Vec<Vec<&BedRecord>> = Vec::new();
+    let mut index: HashMap<(&str, &str), usize> = HashMap::new();
+
+    for record in records {
+        let gene = isoforms.get(&record.name).unwrap_or(&record.name);
+        let idx = *index
+            .entry((record.chrom.as_str(), gene.as_str()))
+            .or_insert_with(|| {
+                blocks.push(Vec::new());
+                blocks.len() - 1
+            });
+        blocks[idx].push(record);
+    }
+
+    blocks.into_iter().flatten().collect()
+}
+
+/// Sorts the features of one transcript by start, keeping the transcript
+/// line first; features starting together are ordered by type.
+pub fn sort_features(lines: &mut [GffRecord]) {
+    lines.sort_by_key(|line| {
+        let rank = match line.feature.as_str() {
+            "transcript" | "mRNA" => 0,
+            "exon" => 1,
+            "five_prime_utr" => 2,
+            "CDS" => 3,
+            "start_codon" => 4,
+            "stop_codon" => 5,
+            "three_prime_utr" => 6,
+            _ => 7,
+        };
+        (rank > 0, line.start, rank)
+    });
+}
+
+/// Converts sorted records and writes them as transcript blocks, grouped by
+/// `gene_blocks`. Chunks of records are converted on the rayon pool while a
+/// single writer thread drains them through a bounded channel, so the output
+/// is the same at any thread count and memory is capped by `CHUNK_SIZE`.
+/// Each gene line in `genes` is written right before its block.
+pub fn write_gff<W: Write + Send>(
+    writer: &mut W,
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    genes: &HashMap<String, GffRecord>,
+    config: &Config,
+) -> io::Result<ConversionStats> {
+    write_chunks(writer, records, isoforms, genes, config, CHUNK_SIZE)
+}
+
+/// Writes one file per chromosome of the `records`, which must be grouped by
+/// chromosome, into `dir`, named after the chromosome, each with its own
+/// header and gene lines. A gene with transcripts on several chromosomes
+/// gets a line in each file, clipped to that file's transcripts. Returns the
+/// written paths with their stats.
+pub fn write_by_chrom(
+    dir: &Path,
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    genes: &HashMap<String, GffRecord>,
+    config: &Config,
+    codec: Codec,
+    buffer_size: usize,
+) -> io::Result<Vec<(PathBuf, ConversionStats)>> {
+    // every file must land in `dir`, so no seqid may act as a path
+    if let Some(record) = records.iter().find(|record| {
+        record.chrom.contains(['/', '\\']) || matches!(record.chrom.as_str(), "" | "." | "..")
+    }) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("chromosome {:?} can't be used as a file name", record.chrom),
+        ));
+    }
+    std::fs::create_dir_all(dir)?;
+
+    records
+        .chunk_by(|a, b| a.chrom == b.chrom)
+        .map(|chrom| {
+            let mut name = format!("{}.{}", chrom[0].chrom, config.format.extension());
+            if codec != Codec::None {
+                name.push_str(".gz");
+            }
+            let path = dir.join(name);
+
+            let mut writer =
+                config
+                    .line_ending
+                    .wrap(create_writer(&path, codec, buffer_size, false)?);
+            comments(&mut writer, config);
+            let genes = clip_genes(chrom, isoforms, genes);
+            let stats = write_gff(&mut writer, chrom, isoforms, &genes, config)
+                .and_then(|stats| writer.flush().map(|_| stats))
+                .map_err(|e| path_error(e, "write", &path))?;
+            Ok((path, stats))
+        })
+        .collect()
+}
+
+/// The lines of `genes` with transcripts in `records`, each moved to their
+/// chromosome and spanning only those transcripts. Lets every output that
+/// holds part of a gene, per chromosome or per coding class, carry its own
+/// gene line for the Parent of its transcripts.
+pub fn clip_genes(
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    genes: &HashMap<String, GffRecord>,
+) -> HashMap<String, GffRecord> {
+    let mut clipped: HashMap<String, GffRecord> = HashMap::new();
+    for record in records {
+        let gene = isoforms.get(&record.name).unwrap_or(&record.name);
+        let Some(line) = genes.get(gene) else {
+            continue;
+        };
+        clipped
+            .entry(gene.clone())
+            .and_modify(|line| {
+                line.start = line.start.min(record.tx_start + 1);
+                line.end = line.end.max(record.tx_end);
+            })
+            .or_insert_with(|| GffRecord {
+                seqid: record.chrom.clone(),
+                start: record.tx_start + 1,
+                end: record.tx_end,
+                ..line.clone()
+            });
+    }
+    clipped
+}
+
+/// 1-based span of each chromosome, written as its `##sequence-region`
+/// pragma: the whole chromosome when its length is in `sizes`, otherwise
+/// the span of its transcripts.
+pub fn sequence_regions<'a>(
+    records: &'a [BedRecord],
+    sizes: &HashMap<String, u32>,
+) -> HashMap<&'a str, (u32, u32)> {
+    let mut regions: HashMap<&str, (u32, u32)> = HashMap::new();
+    for record in records {
+        if let Some(&size) = sizes.get(&record.chrom) {
+            regions.insert(&record.chrom, (1, size));
+            continue;
+        }
+        regions
+            .entry(&record.chrom)
+            .and_modify(|(start, end)| {
+                *start = (*start).min(record.tx_start + 1);
+                *end = (*end).max(record.tx_end);
+            })
+            .or_insert((record.tx_start + 1, record.tx_end));
+    }
+    regions
+}
+
+fn write_chunks<W: Write + Send>(
+    writer: &mut W,
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    genes: &HashMap<String, GffRecord>,
+    config: &Config,
+    chunk_size: usize,
+) -> io::Result<ConversionStats> {
+    type Chunk<'a> = Vec<(&'a BedRecord, Option<Vec<GffRecord>>)>;
+    let (tx, rx) = mpsc::sync_channel::<Chunk>(CHANNEL_BOUND);
+
+    // only formats opened by a ##gff-version pragma take other pragmas, and
+    // --no-header drops them along with the rest of the header
+    let regions = match config.format.pragma() {
+        Some(_) if !config.no_sequence_region && !config.no_header => {
+            sequence_regions(records, &config.chrom_sizes)
+        }
+        _ => HashMap::new(),
+    };
+
+    // phase one groups the records by gene, phase two converts and writes
+    // the blocks
+    let records = gene_blocks(records, isoforms);
+
+    std::thread::scope(|scope| {
+        let handle = scope.spawn(move || -> io::Result<ConversionStats> {
+            let mut seen = HashSet::new();
+            let mut chroms = HashSet::new();
+            let mut stats = ConversionStats::default();
+
+            for chunk in rx {
+                for (record, lines) in chunk {
+                    let Some(mut lines) = lines else {
+                        stats.skipped += 1;
+                        continue;
+                    };
+
+                    if let Some((start, end)) = regions.get(record.chrom.as_str()) {
+                        if chroms.insert(&record.chrom) {
+                            writeln!(
+                                writer,
+                                "##sequence-region {} {} {}",
+                                record.chrom, start, end
+                            )?;
+                        }
+                    }
+
+                    let gene = isoforms.get(&record.name).unwrap_or(&record.name);
+                    if seen.insert(gene) {
+                        if let Some(line) = genes.get(gene) {
+                            stats.count(line);
+                            writeln!(writer, "{}", line)?;
+                        }
+                    }
+
+                    sort_features(&mut lines);
+                    for mut line in lines {
+                        stats.count(&line);
+                        line.feature = config.type_names.name(&line.feature).to_string();
+                        writeln!(writer, "{}", line)?;
+                    }
+                }
+            }
+
+            Ok(stats)
+        });
+
+        for chunk in records.chunks(chunk_size) {
+            let converted = chunk
+                .par_iter()
+                .map(|&record| (record, to_gff(record, isoforms, config).ok()))
+                .collect::<Vec<_>>();
+
+            // the writer only hangs up on error, which join() reports
+            if tx.send(converted).is_err() {
+                break;
+            }
+        }
+        drop(tx);
+
+        handle.join().expect("writer thread panicked")
+    })
+}
+
+/// Converts a BED stream into GFF/GTF written to `out`, header included,
+/// without touching the filesystem. Isoforms are read as (gene, transcript)
+/// lines; without them each transcript is its own gene. A record whose CDS
+/// does not lie within its exons is an error, as with `--strict`, and so is
+/// a transcript missing from the isoforms. Duplicate names and genes split
+/// across chromosomes are handled as on the command line.
+///
+/// ```
+/// use bed2gff::{bed2gff_stream, Config};
+///
+/// let bed = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,\n";
+/// let isoforms = "GENE1\tTX1\n";
+/// let mut out = Vec::new();
+///
+/// let stats = bed2gff_stream(bed.as_bytes(), Some(isoforms.as_bytes()), &mut out, &Config::default())
+///     .unwrap();
+/// let gff = String::from_utf8(out).unwrap();
+///
+/// assert!(gff.starts_with("##gff-version 3\n"));
+/// assert!(gff.contains("\tgene\t101\t500\t.\t+\t.\tID=GENE1;gene_id=GENE1\n"));
+/// assert_eq!((stats.genes, stats.transcripts, stats.exons), (1, 1, 2));
+/// ```
+pub fn bed2gff_stream<R: BufRead, I: BufRead, W: Write + Send>(
+    mut bed: R,
+    isoforms: Option<I>,
+    out: &mut W,
+    config: &Config,
+) -> Result<ConversionStats, Box<dyn Error>> {
+    let mut text = String::new();
+    bed.read_to_string(&mut text)?;
+    let mut records = parallel_parse(&text)?;
+    for record in &records {
+        record.validate()?;
+    }
+
+    // the same loader and preparation as the command line, minus --strict
+    // and the logging
+    let mut isoforms = match isoforms {
+        Some(mut isoforms) => {
+            let mut text = String::new();
+            isoforms.read_to_string(&mut text)?;
+            let (isoforms, _) = get_isoforms(&text, 1, 2, None, None, None)?;
+            if let Some(record) = records.iter().find(|r| !isoforms.contains_key(&r.name)) {
+                return Err(
+                    format!("Transcript {} not found in isoforms file.", record.name).into(),
+                );
+            }
+            isoforms
+        }
+        None => identity_isoforms(&records),
+    };
+    for (idx, original) in dedup_names(&mut records) {
+        if let Some(gene) = isoforms.get(&original).cloned() {
+            isoforms.insert(records[idx].name.clone(), gene);
+        }
+    }
+    split_genes(&records, &mut isoforms);
+
+    sort_records(&mut records, config.sort, &config.seqid_order);
+    let genes = gene_lines(&records, &isoforms, config);
+
+    comments(out, config);
+    Ok(write_gff(out, &records, &isoforms, &genes, config)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TypeNames;
+    use crate::utils::{
+        custom_par_parse, gene_line, gene_spans, parallel_hash_rev, seqid_ranks, BUFFER_SIZE,
+    };
+
+    const BED: &str = "chr2\t100\t900\tTX3\t0\t+\t150\t800\t0\t2\t200,300,\t0,500,
+chr10\t50\t400\tTX4\t0\t-\t100\t300\t0\t1\t350,\t0,
+chr2\t100\t600\tTX1\t0\t+\t150\t550\t0\t2\t200,100,\t0,400,
+chr2\t300\t700\tTX2\t0\t-\t300\t300\t0\t1\t400,\t0,";
+    const ISOFORMS: &str = "GENE1\tTX1\nGENE1\tTX3\nGENE2\tTX2\nGENE3\tTX4";
+
+    fn convert(threads: usize, chunk_size: usize) -> String {
+        let mut records = BED
+            .lines()
+            .map(|line| BedRecord::parse(line).unwrap())
+            .collect::<Vec<_>>();
+        sort_records(&mut records, SortOrder::Natural, &HashMap::new());
+
+        let isoforms = parallel_hash_rev(ISOFORMS);
+        let genes = gene_spans(&isoforms, &custom_par_parse(&records).unwrap())
+            .iter()
+            .map(|(gene, span)| {
+                (
+                    gene.clone(),
+                    gene_line(gene, span, None, &isoforms, &Config::default()),
+                )
+            })
+            .collect();
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+
+        let mut out = Vec::new();
+        pool.install(|| {
+            write_chunks(
+                &mut out,
+                &records,
+                &isoforms,
+                &genes,
+                &Config::default(),
+                chunk_size,
+            )
+        })
+        .unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn chunked_output_is_deterministic() {
+        let expected = convert(1, CHUNK_SIZE);
+        for threads in [2, 4, 8] {
+            for chunk_size in [1, 2, CHUNK_SIZE] {
+                assert_eq!(convert(threads, chunk_size), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn sorted_records_need_no_reparse() {
+        let isoforms = parallel_hash_rev(ISOFORMS);
+        let write = |records: &[BedRecord]| {
+            let mut out = Vec::new();
+            write_gff(
+                &mut out,
+                records,
+                &isoforms,
+                &HashMap::new(),
+                &Config::default(),
+            )
+            .unwrap();
+            out
+        };
+
+        let mut once = BED
+            .lines()
+            .map(|line| BedRecord::parse(line).unwrap())
+            .collect::<Vec<_>>();
+        sort_records(&mut once, SortOrder::Natural, &HashMap::new());
+
+        // sorting the text and parsing it again must give the same output
+        let twice = once
+            .iter()
+            .map(|record| {
+                let line = BED.lines().find(|l| l.contains(&record.name)).unwrap();
+                BedRecord::parse(line).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(once, twice);
+        assert_eq!(write(&once), write(&twice));
+    }
+
+    #[test]
+    fn fixture_stats() {
+        let mut records = BED
+            .lines()
+            .map(|line| BedRecord::parse(line).unwrap())
+            .collect::<Vec<_>>();
+        sort_records(&mut records, SortOrder::Natural, &HashMap::new());
+        let isoforms = parallel_hash_rev(ISOFORMS);
+        let config = Config::default();
+        let genes = gene_lines(&records, &isoforms, &config);
+
+        let stats = write_gff(&mut io::sink(), &records, &isoforms, &genes, &config).unwrap();
+
+        assert_eq!(
+            stats,
+            ConversionStats {
+                genes: 3,
+                transcripts: 4,
+                exons: 6,
+                cds: 5,
+                utr: 6,
+                skipped: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn minus_strand_features_ascend() {
+        let line = "chr11\t13934505\t13958243\tTX1\t1000\t-\t13934600\t13958000\t0,0,200\t9\t224,217,228,198,149,142,115,157,49,\t0,1305,2811,5576,10085,14837,18016,19498,23689,";
+        let record = BedRecord::parse(line).unwrap();
+
+        let mut lines = to_gff(&record, &HashMap::new(), &Config::default()).unwrap();
+        sort_features(&mut lines);
+
+        assert_eq!(lines[0].feature, "transcript");
+        assert!(lines[1..].windows(2).all(|w| w[0].start <= w[1].start));
+
+        // start_codon shares its start with the first CDS on the plus strand
+        let line = "chr15\t81000922\t81005788\tTX2\t0\t+\t81002271\t81003360\t0\t1\t4866,\t0,";
+        let mut lines = to_gff(
+            &BedRecord::parse(line).unwrap(),
+            &HashMap::new(),
+            &Config::default(),
+        )
+        .unwrap();
+        lines.reverse();
+        sort_features(&mut lines);
+        let features = lines.iter().map(|l| l.feature.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            features,
+            vec![
+                "transcript",
+                "exon",
+                "five_prime_utr",
+                "CDS",
+                "start_codon",
+                "stop_codon",
+                "three_prime_utr"
+            ]
+        );
+    }
+
+    #[test]
+    fn one_file_per_chrom() {
+        let dir = std::env::temp_dir().join("bed2gff_one_file_per_chrom");
+        let mut records = BED
+            .lines()
+            .map(|line| BedRecord::parse(line).unwrap())
+            .collect::<Vec<_>>();
+        sort_records(&mut records, SortOrder::Natural, &HashMap::new());
+        let isoforms = parallel_hash_rev(ISOFORMS);
+        let config = Config::default();
+        let genes = gene_lines(&records, &isoforms, &config);
+
+        let written = write_by_chrom(
+            &dir,
+            &records,
+            &isoforms,
+            &genes,
+            &config,
+            Codec::None,
+            BUFFER_SIZE,
+        )
+        .unwrap();
+
+        assert_eq!(
+            written
+                .iter()
+                .map(|(path, stats)| (
+                    path.file_name().unwrap().to_str().unwrap(),
+                    stats.genes,
+                    stats.transcripts
+                ))
+                .collect::<Vec<_>>(),
+            vec![("chr2.gff", 2, 3), ("chr10.gff", 1, 1)]
+        );
+        for (path, _) in &written {
+            let gff = std::fs::read_to_string(path).unwrap();
+            assert!(gff.starts_with("##gff-version 3\n#provider: bed2gff\n"));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn no_gene_lines() {
+        let records = BED
+            .lines()
+            .map(|line| BedRecord::parse(line).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut out = Vec::new();
+        write_gff(
+            &mut out,
+            &records,
+            &HashMap::new(),
+            &HashMap::new(),
+            &Config {
+                no_gene: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(!out.contains("\tgene\t"));
+        assert!(out
+            .lines()
+            .filter(|line| line.contains("\ttranscript\t"))
+            .all(|line| !line.contains("Parent=")));
+        let problems = crate::validate::validate_gff(out.as_bytes()).unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn blocks_follow_sorted_order() {
+        let out = convert(2, 1);
+        let order = out
+            .lines()
+            .filter(|line| line.contains("\ttranscript\t") || line.contains("\tgene\t"))
+            .map(|line| line.split("ID=").nth(1).unwrap().split(';').next().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            order,
+            vec!["GENE1", "TX1", "TX3", "GENE2", "TX2", "GENE3", "TX4"]
+        );
+    }
+
+    #[test]
+    fn gene_line_per_chrom_file() {
+        let dir = std::env::temp_dir().join("bed2gff_gene_line_per_chrom_file");
+        let mut records = [
+            "chr1\t100\t300\tTX1\t0\t+\t100\t100\t0\t1\t200,\t0,",
+            "chr2\t500\t900\tTX2\t0\t+\t500\t500\t0\t1\t400,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+        sort_records(&mut records, SortOrder::Natural, &HashMap::new());
+        let isoforms = parallel_hash_rev("GENE1\tTX1\nGENE1\tTX2");
+        let config = Config::default();
+        let genes = gene_lines(&records, &isoforms, &config);
+
+        let written = write_by_chrom(
+            &dir,
+            &records,
+            &isoforms,
+            &genes,
+            &config,
+            Codec::None,
+            BUFFER_SIZE,
+        )
+        .unwrap();
+
+        let gene_spans = written
+            .iter()
+            .map(|(path, _)| {
+                let gff = std::fs::read_to_string(path).unwrap();
+                let line = gff.lines().find(|line| line.contains("\tgene\t")).unwrap();
+                let fields = line.split('\t').collect::<Vec<_>>();
+                (
+                    fields[0].to_string(),
+                    fields[3].to_string(),
+                    fields[4].to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            gene_spans,
+            vec![
+                ("chr1".to_string(), "101".to_string(), "300".to_string()),
+                ("chr2".to_string(), "501".to_string(), "900".to_string()),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gene_line_per_coding_class() {
+        let mut records = [
+            "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,",
+            "chr1\t50\t300\tTX2\t0\t+\t50\t50\t0\t1\t250,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+        sort_records(&mut records, SortOrder::Natural, &HashMap::new());
+        let isoforms = parallel_hash_rev("GENE1\tTX1\nGENE1\tTX2");
+        let config = Config::default();
+        let genes = gene_lines(&records, &isoforms, &config);
+
+        let (coding, noncoding): (Vec<_>, Vec<_>) = records
+            .into_iter()
+            .partition(|record| record.cds_start < record.cds_end);
+        for (records, span) in [(coding, ("101", "500")), (noncoding, ("51", "300"))] {
+            let mut out = Vec::new();
+            let genes = clip_genes(&records, &isoforms, &genes);
+            write_gff(&mut out, &records, &isoforms, &genes, &config).unwrap();
+
+            let gff = String::from_utf8(out).unwrap();
+            let gene = gff.lines().find(|line| line.contains("\tgene\t")).unwrap();
+            let fields = gene.split('\t').collect::<Vec<_>>();
+            assert_eq!((fields[3], fields[4]), span);
+            let problems = crate::validate::validate_gff(gff.as_bytes()).unwrap();
+            assert!(problems.is_empty(), "{:?}", problems);
+        }
+    }
+
+    #[test]
+    fn chrom_files_stay_in_dir() {
+        let dir = std::env::temp_dir().join("bed2gff_chrom_files_stay_in_dir");
+        for chrom in ["../chr1", "chr1/x", ".."] {
+            let line = format!("{}\t100\t300\tTX1\t0\t+\t100\t100\t0\t1\t200,\t0,", chrom);
+            let records = vec![BedRecord::parse(&line).unwrap()];
+            let err = write_by_chrom(
+                &dir,
+                &records,
+                &HashMap::new(),
+                &HashMap::new(),
+                &Config::default(),
+                Codec::None,
+                BUFFER_SIZE,
+            )
+            .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn sequence_region_per_chrom() {
+        let out = convert(2, 1);
+        let regions = out
+            .lines()
+            .filter(|line| line.starts_with("##sequence-region"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            regions,
+            vec![
+                "##sequence-region chr2 101 900",
+                "##sequence-region chr10 51 400"
+            ]
+        );
+
+        let records = BED
+            .lines()
+            .map(|line| BedRecord::parse(line).unwrap())
+            .collect::<Vec<_>>();
+        let sizes = HashMap::from([("chr2".to_string(), 1000)]);
+        let regions = sequence_regions(&records, &sizes);
+        assert_eq!(regions["chr2"], (1, 1000));
+        assert_eq!(regions["chr10"], (51, 400));
+    }
+
+    #[test]
+    fn chrom_sort_orders() {
+        let chroms = |order| {
+            let mut records = BED
+                .lines()
+                .map(|line| BedRecord::parse(line).unwrap())
+                .collect::<Vec<_>>();
+            sort_records(&mut records, order, &HashMap::new());
+            records
+                .iter()
+                .map(|record| record.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(chroms(SortOrder::Natural), ["TX1", "TX3", "TX2", "TX4"]);
+        assert_eq!(
+            chroms(SortOrder::Lexicographic),
+            ["TX4", "TX1", "TX3", "TX2"]
+        );
+        assert_eq!(chroms(SortOrder::None), ["TX3", "TX4", "TX1", "TX2"]);
+    }
+
+    #[test]
+    fn presorted_input_needs_no_sort() {
+        let parse = |bed: &str| {
+            bed.lines()
+                .map(|line| BedRecord::parse(line).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let mut sorted = parse(BED);
+        sort_records(&mut sorted, SortOrder::Natural, &HashMap::new());
+
+        // BED with its lines already in sorted order, converted unsorted
+        let presorted = parse(
+            &sorted
+                .iter()
+                .map(|record| {
+                    BED.lines()
+                        .find(|line| line.split('\t').nth(3) == Some(record.name.as_str()))
+                        .unwrap()
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+        assert!(unsorted_records(&presorted, SortOrder::Natural, &HashMap::new()).is_empty());
+
+        let isoforms = parallel_hash_rev(ISOFORMS);
+        let config = Config::default();
+        let convert = |records: &[BedRecord]| {
+            let genes = gene_lines(records, &isoforms, &config);
+            let mut out = Vec::new();
+            write_gff(&mut out, records, &isoforms, &genes, &config).unwrap();
+            out
+        };
+        assert_eq!(convert(&presorted), convert(&sorted));
+
+        // BED lists chr2 before chr10, then chr1: TX1 breaks the order
+        let unsorted = parse(BED);
+        assert_eq!(
+            unsorted_records(&unsorted, SortOrder::Natural, &HashMap::new()),
+            [2]
+        );
+        assert!(unsorted_records(&unsorted, SortOrder::None, &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn interleaved_genes_in_blocks() {
+        let mut records = "chr1\t100\t200\tA1\t0\t+\t100\t100\t0\t1\t100,\t0,
+chr1\t150\t250\tB1\t0\t+\t150\t150\t0\t1\t100,\t0,
+chr1\t300\t400\tA2\t0\t+\t300\t300\t0\t1\t100,\t0,
+chr1\t350\t450\tB2\t0\t+\t350\t350\t0\t1\t100,\t0,"
+            .lines()
+            .map(|line| BedRecord::parse(line).unwrap())
+            .collect::<Vec<_>>();
+        sort_records(&mut records, SortOrder::Natural, &HashMap::new());
+        let isoforms = parallel_hash_rev("GENEA\tA1\nGENEA\tA2\nGENEB\tB1\nGENEB\tB2");
+        let genes = gene_lines(&records, &isoforms, &Config::default());
+
+        let mut out = Vec::new();
+        write_gff(&mut out, &records, &isoforms, &genes, &Config::default()).unwrap();
+        let ids = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .filter(|line| matches!(line.split('\t').nth(2), Some("gene" | "transcript")))
+            .map(|line| {
+                line.split('\t')
+                    .nth(8)
+                    .unwrap()
+                    .split(';')
+                    .next()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            ["ID=GENEA", "ID=A1", "ID=A2", "ID=GENEB", "ID=B1", "ID=B2"]
+        );
+    }
+
+    #[test]
+    fn straddling_gene_stays_nested() {
+        // GENEA straddles GENEB: its transcripts lie on both sides of it
+        let mut records = "chr1\t500\t600\tA2\t0\t+\t500\t500\t0\t1\t100,\t0,
+chr1\t300\t400\tB1\t0\t-\t300\t300\t0\t1\t100,\t0,
+chr1\t100\t200\tA1\t0\t+\t100\t100\t0\t1\t100,\t0,"
+            .lines()
+            .map(|line| BedRecord::parse(line).unwrap())
+            .collect::<Vec<_>>();
+        sort_records(&mut records, SortOrder::Natural, &HashMap::new());
+        let isoforms = parallel_hash_rev("GENEA\tA1\nGENEA\tA2\nGENEB\tB1");
+        let config = Config::default();
+        let genes = gene_lines(&records, &isoforms, &config);
+
+        let mut out = Vec::new();
+        write_gff(&mut out, &records, &isoforms, &genes, &config).unwrap();
+        assert!(crate::validate::validate_gff(out.as_slice())
+            .unwrap()
+            .is_empty());
+
+        let lines = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.parse::<GffRecord>().unwrap())
+            .filter(|line| line.feature == "gene" || line.feature == "transcript")
+            .collect::<Vec<_>>();
+        let spans = lines
+            .iter()
+            .map(|line| (line.attribute("ID").unwrap(), line.start, line.end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            spans,
+            [
+                ("GENEA", 101, 600),
+                ("A1", 101, 200),
+                ("A2", 501, 600),
+                ("GENEB", 301, 400),
+                ("B1", 301, 400),
+            ]
+        );
+
+        // every transcript sits inside the gene line opening its block
+        let mut gene = None;
+        for line in &lines {
+            match line.feature.as_str() {
+                "gene" => gene = Some(line),
+                _ => {
+                    let gene = gene.unwrap();
+                    assert_eq!(line.attribute("Parent"), gene.attribute("ID"));
+                    assert!(gene.start <= line.start && line.end <= gene.end);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn seqid_order_file() {
+        let mut records = "chr1\t100\t200\tTX1\t0\t+\t100\t100\t0\t1\t100,\t0,
+chrM\t100\t200\tTX2\t0\t+\t100\t100\t0\t1\t100,\t0,
+chrUn_1\t100\t200\tTX3\t0\t+\t100\t100\t0\t1\t100,\t0,
+chr2\t100\t200\tTX4\t0\t+\t100\t100\t0\t1\t100,\t0,
+chr10\t100\t200\tTX5\t0\t+\t100\t100\t0\t1\t100,\t0,"
+            .lines()
+            .map(|line| BedRecord::parse(line).unwrap())
+            .collect::<Vec<_>>();
+
+        let seqids = seqid_ranks("chrM\nchr2\nchr1\n");
+        sort_records(&mut records, SortOrder::Natural, &seqids);
+        let chroms = records
+            .iter()
+            .map(|record| record.chrom.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(chroms, ["chrM", "chr2", "chr1", "chr10", "chrUn_1"]);
+    }
+
+    #[test]
+    fn type_names_agree() {
+        let mut records = BED
+            .lines()
+            .map(|line| BedRecord::parse(line).unwrap())
+            .collect::<Vec<_>>();
+        sort_records(&mut records, SortOrder::Natural, &HashMap::new());
+        let isoforms = parallel_hash_rev(ISOFORMS);
+
+        for type_names in [TypeNames::So, TypeNames::Lowercase, TypeNames::Abbreviated] {
+            let config = Config {
+                type_names,
+                ..Default::default()
+            };
+
+            let mut out = Vec::new();
+            let stats = write_gff(&mut out, &records, &isoforms, &HashMap::new(), &config).unwrap();
+            let written = String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .filter(|line| !line.starts_with('#'))
+                .map(|line| line.split('\t').nth(2).unwrap().to_string())
+                .collect::<Vec<_>>();
+            let iterated = crate::lines::features(&records, &isoforms, &config)
+                .map(|line| line.feature)
+                .collect::<Vec<_>>();
+
+            assert_eq!(written, iterated);
+            assert_eq!(stats.cds, 5);
+            assert_eq!(stats.utr, 6);
+            let cds = type_names.name("CDS");
+            let utr = type_names.name("five_prime_utr");
+            assert!(written.iter().any(|feature| feature == cds));
+            assert!(written.iter().any(|feature| feature == utr));
+        }
+
+        assert_eq!(TypeNames::Lowercase.name("CDS"), "cds");
+        assert_eq!(TypeNames::Abbreviated.name("three_prime_utr"), "3UTR");
+    }
+
+    #[test]
+    fn no_header_starts_with_feature() {
+        let bed = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,\n";
+        let convert = |no_header: bool| {
+            let config = Config {
+                no_header,
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            bed2gff_stream(bed.as_bytes(), None::<&[u8]>, &mut out, &config).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(convert(false).lines().next(), Some("##gff-version 3"));
+        let gff = convert(true);
+        assert!(!gff.contains('#'));
+        assert!(gff.starts_with("chr1\tbed2gff\tgene\t101\t500\t"));
+    }
+
+    #[test]
+    fn stream_rejects_cds_outside_exons() {
+        let bed = "chr1\t100\t500\tTX1\t0\t+\t250\t450\t0\t2\t100,200,\t0,200,\n";
+        let mut out = Vec::new();
+        let err = bed2gff_stream(bed.as_bytes(), None::<&[u8]>, &mut out, &Config::default())
+            .unwrap_err();
+
+        assert!(err.to_string().contains("TX1"));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn stream_rejects_unmapped_transcripts() {
+        let bed = "chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,
+chr1\t100\t500\tTX2\t0\t+\t100\t100\t0\t1\t400,\t0,\n";
+        let convert = |isoforms: &str| {
+            let mut out = Vec::new();
+            bed2gff_stream(
+                bed.as_bytes(),
+                Some(isoforms.as_bytes()),
+                &mut out,
+                &Config::default(),
+            )
+            .map(|_| String::from_utf8(out).unwrap())
+        };
+
+        assert_eq!(
+            convert("GENE1\tTX1\n").unwrap_err().to_string(),
+            "Transcript TX2 not found in isoforms file."
+        );
+        assert!(convert("\n").is_err());
+        assert!(convert("GENE1\tTX1\nGENE1\tTX2\n").is_ok());
+    }
+
+    #[test]
+    fn stream_splits_genes_across_chroms() {
+        let bed = "chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,
+chr2\t100\t500\tTX2\t0\t+\t100\t100\t0\t1\t400,\t0,\n";
+        let mut out = Vec::new();
+        bed2gff_stream(
+            bed.as_bytes(),
+            Some("GENE1\tTX1\nGENE1\tTX2\n".as_bytes()),
+            &mut out,
+            &Config::default(),
+        )
+        .unwrap();
+        let gff = String::from_utf8(out).unwrap();
+
+        assert!(gff.contains("chr1\tbed2gff\tgene\t101\t500\t.\t+\t.\tID=GENE1.chr1;"));
+        assert!(gff.contains("chr2\tbed2gff\tgene\t101\t500\t.\t+\t.\tID=GENE1.chr2;"));
+        let problems = crate::validate::validate_gff(gff.as_bytes()).unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+}
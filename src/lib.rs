@@ -1,5 +1,5 @@
 pub mod bed;
-pub use bed::BedRecord;
+pub use bed::{BedRecord, NameSource, ParseError};
 
 pub mod codon;
 pub use codon::*;
@@ -7,8 +7,20 @@ pub use codon::*;
 pub mod lines;
 pub use lines::*;
 
+pub mod gff;
+pub use gff::*;
+
 pub mod utils;
 pub use utils::*;
 
 pub mod cli;
 pub use cli::*;
+
+pub mod config;
+pub use config::*;
+
+pub mod validate;
+pub use validate::*;
+
+pub mod ffi;
+pub use ffi::*;
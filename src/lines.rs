@@ -1,145 +1,1183 @@
 use crate::bed::BedRecord;
 use crate::codon::*;
+use crate::config::{render_template, Config, Feature, StopInCds, TypeNames};
+use crate::gff::{sort_features, GffRecord};
 
 use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::error::Error;
 
+#[allow(clippy::too_many_arguments)]
 pub fn build_gff_line(
     record: &BedRecord,
-    gene: &String,
+    gene: &str,
+    gene_id: Option<&str>,
     gene_type: &str,
     exon_start: u32,
     exon_end: u32,
-    frame: u32,
+    frame: i16,
     exon: i16,
-    result: &mut Vec<(String, String, u32, u32, String, String, String)>,
+    config: &Config,
+    result: &mut Vec<GffRecord>,
 ) {
     assert!(record.tx_start < record.tx_end);
+    assert!(
+        exon_start < exon_end,
+        "{}: empty {} at {}",
+        record.name,
+        gene_type,
+        exon_start
+    );
 
-    let phase = match frame {
-        0 => "0",
-        1 => "2",
-        2 => "1",
-        _ => ".",
-    };
+    if Feature::of(gene_type).is_some_and(|feature| !config.emits(feature)) {
+        return;
+    }
 
-    let mut attr = String::new();
+    let phase = config.phase_style.column(frame);
 
-    if gene_type == "transcript" {
-        attr.push_str(&format!(
-            "ID={};Parent={};gene_id={};transcript_id={}",
-            record.name, gene, gene, record.name
-        ));
-    } else {
-        if exon >= 0 {
-            let (exon_id, nexon) = if record.strand == "+" {
-                let exon_id = exon + 1;
-                (exon_id as u16, exon + 1)
-            } else {
-                let exon_id = record.exon_count - exon as u16;
-                (exon_id, exon_id as i16)
-            };
+    let mut attrs: Vec<(&str, String)> = Vec::new();
+    let mut score = ".";
+
+    if gene_type == "transcript" || gene_type == "mRNA" {
+        if record.score != "0" {
+            score = &record.score;
+        }
+        attrs.push(("ID", config.id(&record.name)));
+        // without gene lines (--no-gene) there is nothing to point at
+        if let Some(gene_id) = gene_id {
+            attrs.push(("Parent", gene_id.to_string()));
+        }
+        attrs.push(("gene_id", gene.to_string()));
+        attrs.push(("transcript_id", record.name.clone()));
+        if let Some(name) = config.gene_names.get(gene) {
+            attrs.push(("gene_name", name.clone()));
+        }
+        if let Some(biotype) = config.biotype(record) {
+            attrs.push(("transcript_biotype", biotype.to_string()));
+        }
+        attrs.extend(config.xrefs(&record.name));
+        if let Some(rgb) = &record.item_rgb {
+            attrs.push(("color", rgb.clone()));
+        }
+    } else if exon >= 0 {
+        let (exon_id, nexon) = if record.strand != "-" {
+            let exon_id = exon + 1;
+            (exon_id as u16, exon + 1)
+        } else {
+            let exon_id = record.exon_count - exon as u16;
+            (exon_id, exon_id as i16)
+        };
 
-            attr.push_str(&format!(
-                "ID={}:{}.{};Parent={};gene_id={};transcript_id={};exon_number={}",
-                gene_type, record.name, exon_id, record.name, gene, record.name, nexon
-            ));
+        // discontinuous CDS: one feature split over lines sharing an ID
+        let id = if gene_type == "CDS" && config.cds_shared_id {
+            format!("{}:{}", gene_type, record.name)
         } else {
-            let prefix = match gene_type {
-                "five_prime_utr" => "5UTR",
-                "three_prime_utr" => "3UTR",
-                _ => panic!("Invalid gene type"),
-            };
+            format!("{}:{}.{}", gene_type, record.name, exon_id)
+        };
+        attrs.push(("ID", config.id(&id)));
+        attrs.push(("Parent", config.id(&record.name)));
+        attrs.push(("gene_id", gene.to_string()));
+        attrs.push(("transcript_id", record.name.clone()));
+        attrs.push(("exon_number", nexon.to_string()));
 
-            attr.push_str(&format!(
-                "ID={}:{};Parent={};gene_id={};transcript_id={}",
-                prefix, record.name, record.name, gene, record.name
-            ));
+        if gene_type == "CDS" {
+            if let Some(protein) = config.protein_id(&record.name) {
+                attrs.push(("protein_id", protein));
+            }
         }
+    } else {
+        let prefix = match gene_type {
+            "five_prime_utr" | "three_prime_utr" => TypeNames::Abbreviated.name(gene_type),
+            _ => panic!("Invalid gene type"),
+        };
+
+        attrs.push(("ID", config.id(&format!("{}:{}", prefix, record.name))));
+        attrs.push(("Parent", config.id(&record.name)));
+        attrs.push(("gene_id", gene.to_string()));
+        attrs.push(("transcript_id", record.name.clone()));
     }
 
-    result.push((
-        record.chrom.clone(),
-        gene_type.to_string(),
-        exon_start + 1,
-        exon_end,
-        record.strand.clone(),
-        phase.to_string(),
-        attr,
-    ));
+    let attributes = match &config.attr_template {
+        Some(template) => {
+            let exon_number = attrs
+                .iter()
+                .find_map(|(key, value)| (*key == "exon_number").then_some(value.as_str()));
+            render_template(
+                template,
+                &[
+                    ("gene", Some(gene)),
+                    ("tx", Some(&record.name)),
+                    ("exon", exon_number),
+                    ("chrom", Some(&record.chrom)),
+                    ("strand", Some(&record.strand)),
+                ],
+            )
+        }
+        None => config.format.attributes(&attrs),
+    };
+
+    result.push(GffRecord {
+        seqid: record.chrom.clone(),
+        source: config.source().to_string(),
+        feature: gene_type.to_string(),
+        start: exon_start + 1,
+        end: exon_end,
+        score: score.to_string(),
+        strand: record.strand.clone(),
+        phase: phase.to_string(),
+        attributes,
+    });
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn write_features(
     i: usize,
     record: &BedRecord,
-    gene: &String,
-    // first_utr_end: u32,
+    gene: &str,
+    first_utr_end: u32,
     cds_start: u32,
     cds_end: u32,
-    // last_utr_start: u32,
-    frame: u32,
-    result: &mut Vec<(String, String, u32, u32, String, String, String)>,
+    last_utr_start: u32,
+    frame: i16,
+    config: &Config,
+    result: &mut Vec<GffRecord>,
 ) {
     let exon_start = record.exon_start[i];
     let exon_end = record.exon_end[i];
 
-    // if exon_start < first_utr_end {
-    //     let end = min(exon_end, first_utr_end);
-    //     let utr_type = if record.strand == "+" {
-    //         "five_prime_utr"
-    //     } else {
-    //         "three_prime_utr"
-    //     };
-    //     build_gff_line(record, gene, utr_type, exon_start, end, frame, -1, result);
-    // }
+    // both guards keep UTRs non-empty: exon_start < end and start < exon_end
+    if !config.no_utr && exon_start < first_utr_end {
+        let end = min(exon_end, first_utr_end);
+        let utr_type = if record.strand == "+" {
+            "five_prime_utr"
+        } else {
+            "three_prime_utr"
+        };
+        build_gff_line(
+            record, gene, None, utr_type, exon_start, end, -1, -1, config, result,
+        );
+    }
 
     if record.cds_start < exon_end && exon_start < record.cds_end {
         let start = max(exon_start, cds_start);
         let end = min(exon_end, cds_end);
 
         if start < end {
-            build_gff_line(record, gene, "CDS", start, end, frame, i as i16, result);
+            build_gff_line(
+                record, gene, None, "CDS", start, end, frame, i as i16, config, result,
+            );
         }
     }
 
-    // if exon_end > last_utr_start {
-    //     let start = max(exon_start, last_utr_start);
-    //     let utr_type = if record.strand == "+" {
-    //         "three_prime_utr"
-    //     } else {
-    //         "five_prime_utr"
-    //     };
-    //     build_gff_line(record, gene, utr_type, start, exon_end, frame, -1, result);
-    // }
+    if !config.no_utr && exon_end > last_utr_start {
+        let start = max(exon_start, last_utr_start);
+        let utr_type = if record.strand == "+" {
+            "three_prime_utr"
+        } else {
+            "five_prime_utr"
+        };
+        build_gff_line(
+            record, gene, None, utr_type, start, exon_end, -1, -1, config, result,
+        );
+    }
 }
 
 pub fn write_codon(
     record: &BedRecord,
-    gene: &String,
+    gene: &str,
     gene_type: &str,
     codon: Codon,
-    result: &mut Vec<(String, String, u32, u32, String, String, String)>,
+    config: &Config,
+    result: &mut Vec<GffRecord>,
 ) {
+    let split = codon.start2 < codon.end2;
+
+    // each part's frame counts the codon bases read before it in
+    // translation order
+    let (frame, frame2) = if !split {
+        (0, -1)
+    } else if (codon.start2 < codon.start) == (record.strand == "+") {
+        ((codon.end2 - codon.start2) as i16, 0)
+    } else {
+        (0, (codon.end - codon.start) as i16)
+    };
+
     build_gff_line(
         record,
         gene,
+        None,
         gene_type,
         codon.start,
         codon.end,
-        0,
+        frame,
         codon.index as i16,
+        config,
         result,
     );
 
-    if codon.start2 < codon.end2 {
+    if split {
         build_gff_line(
             record,
             gene,
+            None,
             gene_type,
-            codon.start,
-            codon.end,
             codon.start2,
-            (codon.end - codon.start) as i16,
+            codon.end2,
+            frame2,
+            codon.index2 as i16,
+            config,
             result,
         );
     }
 }
+
+pub fn to_gff(
+    bedline: &BedRecord,
+    isoforms: &HashMap<String, String>,
+    config: &Config,
+) -> Result<Vec<GffRecord>, Box<dyn Error>> {
+    let mut result: Vec<GffRecord> = Vec::new();
+
+    let gene = if !isoforms.is_empty() {
+        match isoforms.get(&bedline.name) {
+            Some(g) => g,
+            None => {
+                return Err(format!("Gene {} not found in isoforms file.", bedline.name).into());
+            }
+        }
+    } else {
+        &bedline.name
+    };
+    // a transcript with no isoforms map is a gene of the same name
+    let gene_id = (!config.no_gene)
+        .then(|| config.gene_id(gene, isoforms.is_empty() || isoforms.contains_key(gene)));
+
+    build_gff_line(
+        bedline,
+        gene,
+        gene_id.as_deref(),
+        config.transcript_type(bedline),
+        bedline.tx_start,
+        bedline.tx_end,
+        -1,
+        -1,
+        config,
+        &mut result,
+    );
+
+    // noncoding (thickStart == thickEnd) or unstranded (a codon can't be
+    // oriented): no codon, CDS or UTR logic at all
+    if bedline.cds_start >= bedline.cds_end || !matches!(bedline.strand.as_str(), "+" | "-") {
+        for i in 0..bedline.exon_start.len() {
+            build_gff_line(
+                bedline,
+                gene,
+                None,
+                "exon",
+                bedline.exon_start[i],
+                bedline.exon_end[i],
+                -1,
+                i as i16,
+                config,
+                &mut result,
+            );
+        }
+        return Ok(result);
+    }
+
+    let (fcodon, lcodon) = match config.relax_frame {
+        true => (first_boundary_codon(bedline), last_boundary_codon(bedline)),
+        false => (first_codon(bedline), last_codon(bedline)),
+    };
+    let fcodon = fcodon.ok_or_else(|| format!("No start codon found for {}.", bedline.name))?;
+    let lcodon = lcodon.ok_or_else(|| format!("No stop codon found for {}.", bedline.name))?;
+    // UTRs end at the thick bounds, outside the start and stop codons
+    let first_utr_end = bedline.cds_start;
+    let last_utr_start = bedline.cds_end;
+    let frames = bedline.get_frames();
+
+    let exclude_stop = config.stop_in_cds == StopInCds::Exclude;
+    let cds_end: u32 = if exclude_stop && bedline.strand == "+" && codon_complete(&lcodon) {
+        // the last base before the stop codon, plus one
+        move_pos(bedline, lcodon.end - 1, -3)
+            .map(|pos| pos + 1)
+            .unwrap_or_else(|e| {
+                log::warn!("{}; keeping the BED CDS end", e);
+                bedline.cds_end
+            })
+    } else {
+        bedline.cds_end
+    };
+
+    let cds_start = if exclude_stop && bedline.strand == "-" && codon_complete(&fcodon) {
+        move_pos(bedline, fcodon.start, 3).unwrap_or_else(|e| {
+            log::warn!("{}; keeping the BED CDS start", e);
+            bedline.cds_start
+        })
+    } else {
+        bedline.cds_start
+    };
+
+    for (i, frame) in frames.iter().enumerate() {
+        let (start, end) = (bedline.exon_start[i], bedline.exon_end[i]);
+        let mut cuts = vec![start];
+        if config.utr_as_exon_parts {
+            // UTR and CDS parts of one exon share its ID and exon_number
+            cuts.extend(
+                [first_utr_end, last_utr_start]
+                    .into_iter()
+                    .filter(|&cut| start < cut && cut < end),
+            );
+        }
+        cuts.push(end);
+        for part in cuts.windows(2) {
+            build_gff_line(
+                bedline,
+                gene,
+                None,
+                "exon",
+                part[0],
+                part[1],
+                -1,
+                i as i16,
+                config,
+                &mut result,
+            );
+        }
+        if cds_start < cds_end {
+            write_features(
+                i,
+                bedline,
+                gene,
+                first_utr_end,
+                cds_start,
+                cds_end,
+                last_utr_start,
+                *frame,
+                config,
+                &mut result,
+            );
+        }
+    }
+
+    if bedline.strand != "-" {
+        if codon_complete(&fcodon) {
+            write_codon(bedline, gene, "start_codon", fcodon, config, &mut result);
+        }
+        if codon_complete(&lcodon) {
+            write_codon(bedline, gene, "stop_codon", lcodon, config, &mut result);
+        }
+    } else {
+        if codon_complete(&lcodon) {
+            write_codon(bedline, gene, "start_codon", lcodon, config, &mut result);
+        }
+        if codon_complete(&fcodon) {
+            write_codon(bedline, gene, "stop_codon", fcodon, config, &mut result);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Codon features a coding transcript will be written without, in
+/// translation order (`start_codon`, `stop_codon`), because its CDS does
+/// not open or close on a whole codon. Noncoding and unstranded
+/// transcripts have no codons to miss.
+pub fn missing_codons(record: &BedRecord) -> Vec<&'static str> {
+    if record.cds_start >= record.cds_end || !matches!(record.strand.as_str(), "+" | "-") {
+        return Vec::new();
+    }
+
+    let complete = |codon: Option<Codon>| codon.as_ref().is_some_and(codon_complete);
+    let (first, last) = (complete(first_codon(record)), complete(last_codon(record)));
+    let (start, stop) = if record.strand == "+" {
+        (first, last)
+    } else {
+        (last, first)
+    };
+
+    let mut missing = Vec::new();
+    if !start {
+        missing.push("start_codon");
+    }
+    if !stop {
+        missing.push("stop_codon");
+    }
+    missing
+}
+
+/// Converts one transcript of `gene` into GFF3 lines joined by newlines,
+/// sorted the way the writer sorts them, with the default config.
+///
+/// ```
+/// use bed2gff::{convert_record_to_gff, BedRecord};
+///
+/// let record = BedRecord::parse("chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t2\t100,200,\t0,200,")
+///     .unwrap();
+///
+/// assert_eq!(
+///     convert_record_to_gff(&record, "GENE1"),
+///     "chr1\tbed2gff\ttranscript\t101\t500\t.\t+\t.\tID=TX1;Parent=GENE1;gene_id=GENE1;transcript_id=TX1
+/// chr1\tbed2gff\texon\t101\t200\t.\t+\t.\tID=exon:TX1.1;Parent=TX1;gene_id=GENE1;transcript_id=TX1;exon_number=1
+/// chr1\tbed2gff\texon\t301\t500\t.\t+\t.\tID=exon:TX1.2;Parent=TX1;gene_id=GENE1;transcript_id=TX1;exon_number=2"
+/// );
+/// ```
+pub fn convert_record_to_gff(record: &BedRecord, gene: &str) -> String {
+    let isoforms = HashMap::from([(record.name.clone(), gene.to_string())]);
+    let mut lines =
+        to_gff(record, &isoforms, &Config::default()).expect("transcript is in its own isoforms");
+    sort_features(&mut lines);
+
+    lines
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Lazily converts records into their transcript, exon, CDS and codon
+/// features, one transcript at a time and sorted and named as written. Gene
+/// lines are not included; records missing from a non-empty `isoforms` are
+/// skipped.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use bed2gff::{features, BedRecord, Config};
+///
+/// let record = BedRecord::parse("chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t2\t100,200,\t0,200,")
+///     .unwrap();
+/// let isoforms = HashMap::from([("TX1".to_string(), "GENE1".to_string())]);
+/// let config = Config::default();
+///
+/// let types = features([&record], &isoforms, &config)
+///     .map(|feature| (feature.feature, feature.start, feature.end))
+///     .collect::<Vec<_>>();
+/// assert_eq!(
+///     types,
+///     [
+///         ("transcript".to_string(), 101, 500),
+///         ("exon".to_string(), 101, 200),
+///         ("exon".to_string(), 301, 500)
+///     ]
+/// );
+/// ```
+pub fn features<'a, I>(
+    records: I,
+    isoforms: &'a HashMap<String, String>,
+    config: &'a Config,
+) -> impl Iterator<Item = GffRecord> + 'a
+where
+    I: IntoIterator<Item = &'a BedRecord>,
+    I::IntoIter: 'a,
+{
+    records.into_iter().flat_map(move |record| {
+        let mut lines = to_gff(record, isoforms, config).unwrap_or_default();
+        sort_features(&mut lines);
+        for line in &mut lines {
+            line.feature = config.type_names.name(&line.feature).to_string();
+        }
+        lines
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::config::{check_template, Format, PhaseStyle, ProteinIdFrom};
+    use clap::{Parser, ValueEnum};
+
+    fn cds_phases(style: PhaseStyle) -> Vec<String> {
+        let line = "chr11\t13934505\t13958243\tENST00000674667\t1000\t-\t13934505\t13958243\t0,0,200\t9\t224,217,228,198,149,142,115,157,49,\t0,1305,2811,5576,10085,14837,18016,19498,23689,";
+        let record = BedRecord::parse(line).unwrap();
+        let gene = String::from("ENSG00000110713");
+        let config = Config {
+            phase_style: style,
+            ..Default::default()
+        };
+        let frames = record.get_frames();
+
+        let mut result = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            write_features(
+                i,
+                &record,
+                &gene,
+                record.cds_start,
+                record.cds_start,
+                record.cds_end,
+                record.cds_end,
+                *frame,
+                &config,
+                &mut result,
+            );
+        }
+
+        result.into_iter().map(|line| line.phase).collect()
+    }
+
+    fn convert(format: Format) -> String {
+        let line =
+            "chr15\t81000922\t81005788\tENST00000267984\t0\t+\t81002271\t81003360\t0\t1\t4866,\t0,";
+        let record = BedRecord::parse(line).unwrap();
+        let isoforms = HashMap::from([(record.name.clone(), "GENE1".to_string())]);
+        let config = Config::try_from(&Cli::parse_from([
+            "bed2gff",
+            "-b",
+            "in.bed",
+            "-o",
+            "out.gff",
+            "--format",
+            format.to_possible_value().unwrap().get_name(),
+        ]))
+        .unwrap();
+
+        let mut lines = to_gff(&record, &isoforms, &config).unwrap();
+        lines.sort_by_key(|line| line.start);
+        lines
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect::<String>()
+    }
+
+    #[test]
+    fn gff3_fixture() {
+        assert_eq!(
+            convert(Format::Gff3),
+            "\
+chr15\tbed2gff\ttranscript\t81000923\t81005788\t.\t+\t.\tID=ENST00000267984;Parent=GENE1;gene_id=GENE1;transcript_id=ENST00000267984
+chr15\tbed2gff\texon\t81000923\t81005788\t.\t+\t.\tID=exon:ENST00000267984.1;Parent=ENST00000267984;gene_id=GENE1;transcript_id=ENST00000267984;exon_number=1
+chr15\tbed2gff\tfive_prime_utr\t81000923\t81002271\t.\t+\t.\tID=5UTR:ENST00000267984;Parent=ENST00000267984;gene_id=GENE1;transcript_id=ENST00000267984
+chr15\tbed2gff\tCDS\t81002272\t81003357\t.\t+\t0\tID=CDS:ENST00000267984.1;Parent=ENST00000267984;gene_id=GENE1;transcript_id=ENST00000267984;exon_number=1
+chr15\tbed2gff\tstart_codon\t81002272\t81002274\t.\t+\t0\tID=start_codon:ENST00000267984.1;Parent=ENST00000267984;gene_id=GENE1;transcript_id=ENST00000267984;exon_number=1
+chr15\tbed2gff\tstop_codon\t81003358\t81003360\t.\t+\t0\tID=stop_codon:ENST00000267984.1;Parent=ENST00000267984;gene_id=GENE1;transcript_id=ENST00000267984;exon_number=1
+chr15\tbed2gff\tthree_prime_utr\t81003361\t81005788\t.\t+\t.\tID=3UTR:ENST00000267984;Parent=ENST00000267984;gene_id=GENE1;transcript_id=ENST00000267984
+"
+        );
+    }
+
+    #[test]
+    fn gtf_fixture() {
+        assert_eq!(
+            convert(Format::Gtf),
+            "\
+chr15\tbed2gff\ttranscript\t81000923\t81005788\t.\t+\t.\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\";
+chr15\tbed2gff\texon\t81000923\t81005788\t.\t+\t.\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\"; exon_number \"1\";
+chr15\tbed2gff\tfive_prime_utr\t81000923\t81002271\t.\t+\t.\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\";
+chr15\tbed2gff\tCDS\t81002272\t81003357\t.\t+\t0\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\"; exon_number \"1\";
+chr15\tbed2gff\tstart_codon\t81002272\t81002274\t.\t+\t0\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\"; exon_number \"1\";
+chr15\tbed2gff\tstop_codon\t81003358\t81003360\t.\t+\t0\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\"; exon_number \"1\";
+chr15\tbed2gff\tthree_prime_utr\t81003361\t81005788\t.\t+\t.\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\";
+"
+        );
+    }
+
+    #[test]
+    fn bed6_is_exon_only() {
+        let record = BedRecord::parse_bed6("chr1\t14361\t29370\tm64012/4391\t60\t-").unwrap();
+        let isoforms = HashMap::from([(record.name.clone(), record.name.clone())]);
+
+        let features = to_gff(&record, &isoforms, &Config::default())
+            .unwrap()
+            .into_iter()
+            .map(|line| (line.feature, line.start, line.end))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            features,
+            vec![
+                ("transcript".to_string(), 14362, 29370),
+                ("exon".to_string(), 14362, 29370)
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_source_column() {
+        let line =
+            "chr15\t81000922\t81005788\tENST00000267984\t0\t+\t81002271\t81003360\t0\t1\t4866,\t0,";
+        let record = BedRecord::parse(line).unwrap();
+        let isoforms = HashMap::from([(record.name.clone(), "GENE1".to_string())]);
+        let config = Config {
+            source: Some("TOGA".to_string()),
+            ..Default::default()
+        };
+
+        for line in to_gff(&record, &isoforms, &config).unwrap() {
+            assert_eq!(line.to_string().split('\t').nth(1), Some("TOGA"));
+        }
+    }
+
+    #[test]
+    fn so_terms_mrna_for_coding() {
+        let config = Config {
+            so_terms: true,
+            ..Default::default()
+        };
+
+        for (line, expected) in [
+            (
+                "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t1\t400,\t0,",
+                "mRNA",
+            ),
+            (
+                "chr1\t100\t500\tTX2\t0\t+\t100\t100\t0\t1\t400,\t0,",
+                "transcript",
+            ),
+        ] {
+            let record = BedRecord::parse(line).unwrap();
+            let isoforms = HashMap::from([(record.name.clone(), "GENE1".to_string())]);
+            let lines = to_gff(&record, &isoforms, &config).unwrap();
+            assert_eq!(lines[0].feature, expected);
+            assert_eq!(lines[0].attribute("ID"), Some(record.name.as_str()));
+        }
+    }
+
+    #[test]
+    fn gff2_fixture() {
+        assert_eq!(
+            convert(Format::Gff2),
+            "\
+chr15\tbed2gff\ttranscript\t81000923\t81005788\t.\t+\t.\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\";
+chr15\tbed2gff\texon\t81000923\t81005788\t.\t+\t.\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\"; exon_number \"1\";
+chr15\tbed2gff\tfive_prime_utr\t81000923\t81002271\t.\t+\t.\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\";
+chr15\tbed2gff\tCDS\t81002272\t81003357\t.\t+\t0\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\"; exon_number \"1\";
+chr15\tbed2gff\tstart_codon\t81002272\t81002274\t.\t+\t0\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\"; exon_number \"1\";
+chr15\tbed2gff\tstop_codon\t81003358\t81003360\t.\t+\t0\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\"; exon_number \"1\";
+chr15\tbed2gff\tthree_prime_utr\t81003361\t81005788\t.\t+\t.\tgene_id \"GENE1\"; transcript_id \"ENST00000267984\";
+"
+        );
+    }
+
+    #[test]
+    fn score_and_color_on_transcript() {
+        let line = "chr1\t100\t500\tTX1\t60\t+\t150\t450\t255,0,0\t1\t400,\t0,";
+        let record = BedRecord::parse(line).unwrap();
+        let isoforms = HashMap::from([(record.name.clone(), "GENE1".to_string())]);
+
+        let lines = to_gff(&record, &isoforms, &Config::default()).unwrap();
+        let transcript = lines[0].to_string();
+        assert_eq!(transcript.split('\t').nth(5), Some("60"));
+        assert!(transcript.ends_with(";color=255,0,0"));
+        assert!(lines[1..]
+            .iter()
+            .all(|l| l.score == "." && l.attribute("color").is_none()));
+
+        let bed6 = BedRecord::parse_bed6("chr1\t100\t500\tTX2\t60\t+").unwrap();
+        let lines = to_gff(&bed6, &HashMap::new(), &Config::default()).unwrap();
+        assert_eq!(lines[0].score, "60");
+        assert_eq!(lines[0].attribute("color"), None);
+    }
+
+    #[test]
+    fn noncoding_is_exon_only() {
+        for thick in ["100\t100", "900\t900"] {
+            let line = format!(
+                "chr1\t100\t900\tTX1\t0\t-\t{}\t0\t3\t100,100,100,\t0,400,700,",
+                thick
+            );
+            let record = BedRecord::parse(&line).unwrap();
+
+            let features = to_gff(&record, &HashMap::new(), &Config::default())
+                .unwrap()
+                .into_iter()
+                .map(|line| line.feature)
+                .collect::<Vec<_>>();
+
+            assert_eq!(features, vec!["transcript", "exon", "exon", "exon"]);
+        }
+    }
+
+    #[test]
+    fn protein_id_on_cds_only() {
+        let line =
+            "chr15\t81000922\t81005788\tENST00000267984\t0\t+\t81002271\t81003360\t0\t1\t4866,\t0,";
+        let record = BedRecord::parse(line).unwrap();
+        let isoforms = HashMap::from([(record.name.clone(), "GENE1".to_string())]);
+        let config = Config {
+            protein_id_from: Some(ProteinIdFrom::Transcript),
+            ..Default::default()
+        };
+
+        for line in to_gff(&record, &isoforms, &config).unwrap() {
+            assert_eq!(
+                line.attributes.ends_with(";protein_id=ENST00000267984.p"),
+                line.feature == "CDS"
+            );
+        }
+    }
+
+    #[test]
+    fn cds_gff_phase() {
+        assert_eq!(
+            cds_phases(PhaseStyle::Gff),
+            vec!["2", "0", "0", "0", "2", "0", "1", "2", "0"]
+        );
+    }
+
+    #[test]
+    fn cds_bed_frame() {
+        assert_eq!(
+            cds_phases(PhaseStyle::BedFrame),
+            vec!["1", "0", "0", "0", "1", "0", "2", "1", "0"]
+        );
+    }
+
+    #[test]
+    fn gtf_frame_is_gff_phase() {
+        let line = "chr11\t13934505\t13958243\tTX1\t0\t-\t13934505\t13958243\t0\t3\t224,217,228,\t0,1305,23510,";
+        let record = BedRecord::parse(line).unwrap();
+        let isoforms = HashMap::from([(record.name.clone(), "GENE1".to_string())]);
+        let phases = |format: &str| {
+            let config = Config::try_from(&Cli::parse_from([
+                "bed2gff", "-b", "in.bed", "-o", "out.gff", "--format", format,
+            ]))
+            .unwrap();
+            to_gff(&record, &isoforms, &config)
+                .unwrap()
+                .into_iter()
+                .filter(|line| line.feature == "CDS")
+                .map(|line| line.phase)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(phases("gtf"), phases("gff3"));
+        assert_eq!(phases("gtf"), ["2", "0", "0"]);
+    }
+
+    #[test]
+    fn ids_carry_prefix() {
+        let record =
+            BedRecord::parse("chr1\t100\t900\tTX1\t0\t+\t150\t850\t0\t2\t300,300,\t0,500,")
+                .unwrap();
+        let isoforms = HashMap::from([(record.name.clone(), "GENE1".to_string())]);
+        let config = Config {
+            id_prefix: "run1_".to_string(),
+            ..Default::default()
+        };
+
+        let genes = crate::utils::gene_lines(std::slice::from_ref(&record), &isoforms, &config);
+        let lines = to_gff(&record, &isoforms, &config).unwrap();
+        for line in genes.values().chain(&lines) {
+            assert!(line.attribute("ID").unwrap().starts_with("run1_"));
+            if let Some(parent) = line.attribute("Parent") {
+                assert!(parent.starts_with("run1_"));
+            }
+        }
+        assert_eq!(lines[0].attribute("Parent"), Some("run1_GENE1"));
+    }
+
+    fn codons(line: &str) -> Vec<(String, u32, u32, String)> {
+        let record = BedRecord::parse(line).unwrap();
+        let isoforms = HashMap::from([(record.name.clone(), "GENE1".to_string())]);
+        to_gff(&record, &isoforms, &Config::default())
+            .unwrap()
+            .into_iter()
+            .filter(|line| line.feature.ends_with("_codon"))
+            .map(|line| (line.feature, line.start, line.end, line.phase))
+            .collect()
+    }
+
+    #[test]
+    fn codon_split_by_intron() {
+        let line = |name, cds_start, cds_end| {
+            format!(
+                "chr1\t100\t400\t{}\t0\t+\t{}\t{}\t0\t2\t100,100,\t0,200,",
+                name, cds_start, cds_end
+            )
+        };
+        let part = |feature: &str, start, end, phase: &str| {
+            (feature.to_string(), start, end, phase.to_string())
+        };
+
+        assert_eq!(
+            codons(&line("TX1", 198, 349)),
+            vec![
+                part("start_codon", 199, 200, "0"),
+                part("start_codon", 301, 301, "1"),
+                part("stop_codon", 347, 349, "0"),
+            ]
+        );
+        assert_eq!(
+            codons(&line("TX2", 120, 301)),
+            vec![
+                part("start_codon", 121, 123, "0"),
+                part("stop_codon", 301, 301, "1"),
+                part("stop_codon", 199, 200, "0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn cds_only_features() {
+        let record =
+            BedRecord::parse("chr1\t100\t900\tTX1\t0\t+\t150\t850\t0\t2\t300,300,\t0,500,")
+                .unwrap();
+        let isoforms = HashMap::from([(record.name.clone(), "GENE1".to_string())]);
+        let config = Config {
+            features: Some(vec![Feature::Cds]),
+            ..Default::default()
+        };
+
+        let lines = to_gff(&record, &isoforms, &config).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.feature == "CDS"));
+        assert!(crate::utils::gene_lines(&[record], &isoforms, &config).is_empty());
+    }
+
+    #[test]
+    fn children_point_at_transcript() {
+        let record =
+            BedRecord::parse("chr1\t100\t900\tTX1\t0\t-\t150\t850\t0\t2\t300,300,\t0,500,")
+                .unwrap();
+        let isoforms = HashMap::from([(record.name.clone(), "GENE1".to_string())]);
+        let config = Config::default();
+
+        let genes = crate::utils::gene_lines(std::slice::from_ref(&record), &isoforms, &config);
+        let lines = to_gff(&record, &isoforms, &config).unwrap();
+        for line in &lines[1..] {
+            assert_eq!(line.attribute("Parent"), Some("TX1"), "{}", line);
+            assert_eq!(line.attribute("gene_id"), Some("GENE1"));
+        }
+
+        let gff = genes
+            .values()
+            .chain(&lines)
+            .map(|line| format!("{}\n", line))
+            .collect::<String>();
+        assert!(crate::validate::validate_gff(gff.as_bytes())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn unstranded_is_exon_only() {
+        let record =
+            BedRecord::parse("chr1\t100\t900\tTX1\t0\t.\t150\t850\t0\t2\t300,300,\t0,500,")
+                .unwrap();
+        let lines = to_gff(&record, &HashMap::new(), &Config::default()).unwrap();
+
+        let features = lines
+            .iter()
+            .map(|line| (line.feature.as_str(), line.strand.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            features,
+            [("transcript", "."), ("exon", "."), ("exon", ".")]
+        );
+        assert_eq!(lines[1].attribute("exon_number"), Some("1"));
+
+        let record = BedRecord::parse_bed6("chr1\t100\t900\tTX2\t0\t.").unwrap();
+        assert_eq!(
+            to_gff(&record, &HashMap::new(), &Config::default())
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn single_exon_utrs() {
+        let line = "chr1\t100\t900\tTX1\t0\t-\t300\t600\t0\t1\t800,\t0,";
+        let record = BedRecord::parse(line).unwrap();
+        let utrs = |config: &Config| {
+            to_gff(&record, &HashMap::new(), config)
+                .unwrap()
+                .into_iter()
+                .filter(|line| line.feature.ends_with("_utr"))
+                .map(|line| (line.feature, line.start, line.end))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            utrs(&Config::default()),
+            vec![
+                ("three_prime_utr".to_string(), 101, 300),
+                ("five_prime_utr".to_string(), 601, 900)
+            ]
+        );
+        let no_utr = Config {
+            no_utr: true,
+            ..Default::default()
+        };
+        assert!(utrs(&no_utr).is_empty());
+
+        // CDS flush with the exon: no empty UTR on either side
+        let line = "chr1\t100\t900\tTX2\t0\t+\t100\t900\t0\t1\t800,\t0,";
+        let record = BedRecord::parse(line).unwrap();
+        assert!(to_gff(&record, &HashMap::new(), &Config::default())
+            .unwrap()
+            .iter()
+            .all(|line| !line.feature.ends_with("_utr")));
+    }
+
+    #[test]
+    fn biotypes_on_transcripts() {
+        let config = Config {
+            biotypes: Some(HashMap::from([("TX2".to_string(), "lncRNA".to_string())])),
+            ..Default::default()
+        };
+
+        for (line, expected) in [
+            (
+                "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t1\t400,\t0,",
+                Some("protein_coding"),
+            ),
+            (
+                "chr1\t100\t500\tTX2\t0\t+\t100\t100\t0\t1\t400,\t0,",
+                Some("lncRNA"),
+            ),
+            ("chr1\t100\t500\tTX3\t0\t+\t100\t100\t0\t1\t400,\t0,", None),
+        ] {
+            let record = BedRecord::parse(line).unwrap();
+            let lines = to_gff(&record, &HashMap::new(), &config).unwrap();
+            assert_eq!(lines[0].attribute("transcript_biotype"), expected);
+            assert!(lines[1..]
+                .iter()
+                .all(|line| line.attribute("transcript_biotype").is_none()));
+        }
+    }
+
+    #[test]
+    fn truncated_cds_misses_codons() {
+        // 301 coding bases: the stop codon is not whole
+        let plus = BedRecord::parse("chr1\t100\t500\tTX1\t0\t+\t150\t451\t0\t1\t400,\t0,").unwrap();
+        assert_eq!(missing_codons(&plus), ["stop_codon"]);
+        let lines = to_gff(&plus, &HashMap::new(), &Config::default()).unwrap();
+        assert!(lines.iter().any(|line| line.feature == "start_codon"));
+        assert!(!lines.iter().any(|line| line.feature == "stop_codon"));
+
+        // frames count from the start codon, so the minus strand loses its
+        // stop codon too, at the low end
+        let minus =
+            BedRecord::parse("chr1\t100\t500\tTX2\t0\t-\t150\t451\t0\t1\t400,\t0,").unwrap();
+        assert_eq!(missing_codons(&minus), ["stop_codon"]);
+        let lines = to_gff(&minus, &HashMap::new(), &Config::default()).unwrap();
+        let start = lines
+            .iter()
+            .find(|line| line.feature == "start_codon")
+            .unwrap();
+        assert_eq!((start.start, start.end), (449, 451));
+
+        let whole =
+            BedRecord::parse("chr1\t100\t500\tTX3\t0\t+\t150\t450\t0\t1\t400,\t0,").unwrap();
+        assert!(missing_codons(&whole).is_empty());
+    }
+
+    #[test]
+    fn exon_number_is_its_own_attribute() {
+        let record =
+            BedRecord::parse("chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,")
+                .unwrap();
+
+        for format in [Format::Gff3, Format::Gtf] {
+            let config = Config {
+                format,
+                ..Default::default()
+            };
+            let lines = to_gff(&record, &HashMap::new(), &config).unwrap();
+
+            for line in lines.iter().filter(|line| line.feature == "exon") {
+                let pairs = line
+                    .attributes
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|kv| !kv.is_empty())
+                    .map(|kv| {
+                        let (key, value) = kv.split_once(['=', ' ']).unwrap();
+                        (key, value.trim_matches('"'))
+                    })
+                    .collect::<HashMap<_, _>>();
+                assert_eq!(pairs["transcript_id"], "TX1");
+                assert!(matches!(pairs["exon_number"], "1" | "2"));
+            }
+        }
+    }
+
+    #[test]
+    fn attribute_template() {
+        let record =
+            BedRecord::parse("chr1\t100\t500\tTX1\t0\t-\t100\t100\t0\t2\t100,200,\t0,200,")
+                .unwrap();
+        let config = Config {
+            attr_template: Some(
+                "gene_id={gene};transcript_id={tx};exon_number={exon};loc={chrom}:{strand}"
+                    .to_string(),
+            ),
+            ..Default::default()
+        };
+        let isoforms = HashMap::from([("TX1".to_string(), "GENE1".to_string())]);
+        let lines = to_gff(&record, &isoforms, &config).unwrap();
+
+        assert_eq!(
+            lines[0].attributes,
+            "gene_id=GENE1;transcript_id=TX1;loc=chr1:-"
+        );
+        assert_eq!(
+            lines[1].attributes,
+            "gene_id=GENE1;transcript_id=TX1;exon_number=2;loc=chr1:-"
+        );
+
+        assert!(check_template("ID={tx};Parent={gene}").is_ok());
+        assert_eq!(
+            check_template("ID={transcript}").unwrap_err(),
+            "unknown placeholder {transcript} (expected one of {gene}, {tx}, {exon}, {chrom}, {strand})"
+        );
+        assert!(check_template("ID={tx").is_err());
+        assert!(check_template("ID=tx}").is_err());
+    }
+
+    #[test]
+    fn stop_in_cds_policies() {
+        let cds_span = |strand: &str, stop_in_cds| {
+            let record = BedRecord::parse(&format!(
+                "chr1\t100\t500\tTX1\t0\t{}\t150\t450\t0\t1\t400,\t0,",
+                strand
+            ))
+            .unwrap();
+            let config = Config {
+                stop_in_cds,
+                ..Default::default()
+            };
+            let lines = to_gff(&record, &HashMap::new(), &config).unwrap();
+            let cds = lines.iter().find(|line| line.feature == "CDS").unwrap();
+            (cds.start, cds.end)
+        };
+
+        assert_eq!(cds_span("+", StopInCds::Exclude), (151, 447));
+        assert_eq!(cds_span("+", StopInCds::Include), (151, 450));
+        assert_eq!(cds_span("-", StopInCds::Exclude), (154, 450));
+        assert_eq!(cds_span("-", StopInCds::Include), (151, 450));
+    }
+
+    #[test]
+    fn codons_span_three_bases() {
+        // BED thick bounds 150-450: the codons are BED 150-153 and 447-450,
+        // GFF 151-153 and 448-450 on either strand
+        for (strand, start, stop) in [("+", (151, 153), (448, 450)), ("-", (448, 450), (151, 153))]
+        {
+            let record = BedRecord::parse(&format!(
+                "chr1\t100\t500\tTX1\t0\t{}\t150\t450\t0\t1\t400,\t0,",
+                strand
+            ))
+            .unwrap();
+            let lines = to_gff(&record, &HashMap::new(), &Config::default()).unwrap();
+            let span = |feature: &str| {
+                let line = lines.iter().find(|line| line.feature == feature).unwrap();
+                (line.start, line.end)
+            };
+
+            assert_eq!(span("start_codon"), start, "{}", strand);
+            assert_eq!(span("stop_codon"), stop, "{}", strand);
+            for (first, last) in [span("start_codon"), span("stop_codon")] {
+                assert_eq!(last - first + 1, 3);
+            }
+        }
+    }
+
+    #[test]
+    fn relaxed_frame_codons() {
+        // 202 coding bases: the last three are not a whole codon
+        let record =
+            BedRecord::parse("chr1\t100\t500\tTX1\t0\t+\t150\t452\t0\t2\t100,200,\t0,200,")
+                .unwrap();
+        let features = |relax_frame| {
+            let config = Config {
+                relax_frame,
+                ..Default::default()
+            };
+            to_gff(&record, &HashMap::new(), &config)
+                .unwrap()
+                .into_iter()
+                .filter(|line| {
+                    matches!(line.feature.as_str(), "CDS" | "start_codon" | "stop_codon")
+                })
+                .map(|line| (line.feature, line.start, line.end))
+                .collect::<Vec<_>>()
+        };
+
+        let feature = |feature: &str, start, end| (feature.to_string(), start, end);
+        assert_eq!(
+            features(false),
+            [
+                feature("CDS", 151, 200),
+                feature("CDS", 301, 452),
+                feature("start_codon", 151, 153),
+            ]
+        );
+        assert_eq!(
+            features(true),
+            [
+                feature("CDS", 151, 200),
+                feature("CDS", 301, 449),
+                feature("start_codon", 151, 153),
+                feature("stop_codon", 450, 452),
+            ]
+        );
+    }
+
+    #[test]
+    fn cds_lines_share_one_id() {
+        let line = "chr1\t100\t900\tTX1\t0\t-\t150\t750\t0\t3\t200,200,200,\t0,300,600,";
+        let record = BedRecord::parse(line).unwrap();
+        let isoforms = HashMap::from([("TX1".to_string(), "GENE1".to_string())]);
+        let cds_ids = |cds_shared_id: bool| {
+            let config = Config {
+                cds_shared_id,
+                ..Default::default()
+            };
+            to_gff(&record, &isoforms, &config)
+                .unwrap()
+                .into_iter()
+                .filter(|line| line.feature == "CDS")
+                .map(|line| line.attribute("ID").unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(cds_ids(false), vec!["CDS:TX1.3", "CDS:TX1.2", "CDS:TX1.1"]);
+        assert_eq!(cds_ids(true), vec!["CDS:TX1"; 3]);
+    }
+
+    #[test]
+    fn exons_split_at_cds_bounds() {
+        let line = "chr1\t100\t900\tTX1\t0\t+\t150\t750\t0\t3\t200,200,200,\t0,300,600,";
+        let record = BedRecord::parse(line).unwrap();
+        let isoforms = HashMap::from([("TX1".to_string(), "GENE1".to_string())]);
+        let exons = |utr_as_exon_parts: bool| {
+            let config = Config {
+                utr_as_exon_parts,
+                ..Default::default()
+            };
+            to_gff(&record, &isoforms, &config)
+                .unwrap()
+                .into_iter()
+                .filter(|line| line.feature == "exon")
+                .map(|line| {
+                    (
+                        line.start,
+                        line.end,
+                        line.attribute("ID").unwrap().to_string(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            exons(false)
+                .iter()
+                .map(|(start, end, _)| (*start, *end))
+                .collect::<Vec<_>>(),
+            vec![(101, 300), (401, 600), (701, 900)]
+        );
+        assert_eq!(
+            exons(true),
+            vec![
+                (101, 150, "exon:TX1.1".to_string()),
+                (151, 300, "exon:TX1.1".to_string()),
+                (401, 600, "exon:TX1.2".to_string()),
+                (701, 750, "exon:TX1.3".to_string()),
+                (751, 900, "exon:TX1.3".to_string()),
+            ]
+        );
+    }
+}
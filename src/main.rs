@@ -32,7 +32,7 @@
 //! - `input.bed` is the input BED file you want to convert.
 //! - `isoforms.txt` is a file that contains information about isoforms.
 //! - `output.gff3` is the output gff file where the conversion results
-//! will be stored.
+//!   will be stored.
 //!
 //! ## Output
 //!
@@ -47,34 +47,39 @@
 //! visit the [GitHub repository](https://github.com/alejandrogzi/bed2gff).
 //! We welcome your feedback and contributions to enhance this tool.
 
-use std::collections::HashMap;
-use std::error::Error;
-use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::string::String;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
 use std::time::Instant;
 
 use clap::{self, Parser};
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use log::Level;
-use natord::compare;
-use rayon::prelude::*;
 
 use bed2gff::*;
 
-const SOURCE: &str = "bed2gff";
+const MAX_REPORTED_PROBLEMS: usize = 10;
 
 fn main() {
     let args = Cli::parse();
+
+    if !args.quiet {
+        msg();
+    }
+    match &args.log_file {
+        Some(path) => FileLogger::init(path, args.log_level()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => simple_logger::SimpleLogger::new()
+            .with_level(args.log_level())
+            .init()
+            .unwrap(),
+    }
+
     args.check().unwrap_or_else(|e| {
         log::error!("{}", e);
         std::process::exit(1);
     });
 
-    msg();
-    simple_logger::init_with_level(Level::Info).unwrap();
-
     rayon::ThreadPoolBuilder::new()
         .num_threads(args.threads)
         .build_global()
@@ -84,203 +89,512 @@ fn main() {
 
     let start = Instant::now();
     let bmem = max_mem_usage_mb();
+    let mut config = Config::try_from(&args).unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(1);
+    });
 
-    let imap = if !args.no_gene {
-        let isf = reader(&args.isoforms.unwrap()).unwrap_or_else(|_| {
-            let message = format!("Error reading isoforms file",);
-            panic!("{}", message);
-        });
-        get_isoforms(&isf)
-    } else {
-        HashMap::new()
+    let mut imap = match &args.isoforms {
+        Some(path) if !args.no_gene => {
+            let isf = read_file(path, "isoforms file").unwrap_or_else(|e| {
+                log::error!("{}", e);
+                std::process::exit(1);
+            });
+            let name_col = args
+                .attributes
+                .contains(&Attribute::GeneName)
+                .then_some(args.iso_name_col);
+            let (isoforms, names) = get_isoforms(
+                &isf,
+                args.iso_gene_col,
+                args.iso_tx_col,
+                args.iso_priority_col,
+                name_col,
+                args.iso_delim,
+            )
+            .unwrap_or_else(|e| {
+                log::error!("{}", e);
+                std::process::exit(1);
+            });
+            config.gene_names = names;
+            isoforms
+        }
+        _ => HashMap::new(),
     };
 
-    let bed = bed_reader(&args.bed);
-    let gene_track = custom_par_parse(&bed).unwrap_or_else(|_| {
-        let message = format!("Error parsing BED file {}", args.bed.display());
-        panic!("{}", message);
-    });
-
-    let results = bed
-        .par_iter()
-        .filter_map(|record| to_gff(record, &imap).ok())
-        .flatten()
-        .collect::<Vec<_>>();
+    // all inputs are merged before any gene is computed, so gene lines and
+    // their spans are global across files
+    let mut bed = Vec::new();
+    let mut skipped = 0;
+    for path in &args.bed {
+        let (records, malformed) = bed_reader(
+            path,
+            args.skip_malformed,
+            args.bed6,
+            args.whitespace,
+            &args.name_source(),
+        );
+        bed.extend(records);
+        skipped += malformed;
+    }
 
-    let mut blocks = combine_maps_par(&imap, &gene_track);
-    blocks.extend(results);
+    if args.validate_only {
+        let problems = input_problems(&bed, &imap);
+        let genes = bed
+            .iter()
+            .filter_map(|record| match imap.is_empty() {
+                true => Some(&record.name),
+                false => imap.get(&record.name),
+            })
+            .collect::<HashSet<_>>();
+
+        // stderr, so the report never mixes into a GFF written to stdout
+        eprintln!("Records: {}", bed.len());
+        eprintln!("Genes: {}", genes.len());
+        eprintln!("Problems: {}", problems.len());
+        for problem in problems.iter().take(MAX_REPORTED_PROBLEMS) {
+            eprintln!("  {}", problem);
+        }
 
-    blocks.par_sort_unstable_by(|a, b| {
-        let chr_cmp = compare(&a.0, &b.0);
-        if chr_cmp == std::cmp::Ordering::Equal {
-            a.2.cmp(&b.2)
-        } else {
-            chr_cmp
+        if !problems.is_empty() {
+            std::process::exit(1);
         }
-    });
+        return;
+    }
 
-    let writer_boxed: Box<dyn Write> = if args.gz {
-        let file = File::create(&args.output).unwrap();
-        let encoder = GzEncoder::new(file, Compression::default());
-        Box::new(BufWriter::new(encoder))
-    } else {
-        let file = File::create(&args.output).unwrap();
-        Box::new(BufWriter::new(file))
-    };
+    for record in bed.iter_mut() {
+        if let Err(e) = record.validate() {
+            if args.strict {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+            log::warn!("{}; clamping CDS to exon bounds", e);
+            record.clamp_cds();
+        }
+    }
 
-    let mut writer = writer_boxed;
-    comments(&mut writer);
+    for (idx, original) in dedup_names(&mut bed) {
+        let record = &bed[idx];
+        if args.strict {
+            log::error!(
+                "Duplicate transcript name {} at {}:{}-{}.",
+                original,
+                record.chrom,
+                record.tx_start,
+                record.tx_end
+            );
+            std::process::exit(1);
+        }
 
-    for entry in &blocks {
-        writeln!(
-            writer,
-            "{}\t{}\t{}\t{}\t{}\t.\t{}\t{}\t{}",
-            entry.0, SOURCE, entry.1, entry.2, entry.3, entry.4, entry.5, entry.6
-        )
-        .unwrap();
+        log::warn!(
+            "Duplicate transcript name {} at {}:{}-{} renamed to {}.",
+            original,
+            record.chrom,
+            record.tx_start,
+            record.tx_end,
+            record.name
+        );
+        if let Some(gene) = imap.get(&original).cloned() {
+            imap.insert(record.name.clone(), gene);
+        }
     }
 
-    let peak_mem = (max_mem_usage_mb() - bmem).max(0.0);
-    log::info!("Memory usage: {} MB", peak_mem);
-    log::info!("Elapsed: {:.4?} secs", start.elapsed().as_secs_f32())
-}
-
-fn to_gff(
-    bedline: &BedRecord,
-    isoforms: &HashMap<String, String>,
-) -> Result<Vec<(String, String, u32, u32, String, String, String)>, Box<dyn Error>> {
-    let mut result: Vec<(String, String, u32, u32, String, String, String)> = Vec::new();
-
-    let gene = if !isoforms.is_empty() {
-        match isoforms.get(&bedline.name) {
-            Some(g) => g,
-            None => {
-                log::error!("Gene {} not found in isoforms file.", bedline.name);
-                std::process::exit(1)
+    if let Some(pattern) = &args.gene_regex {
+        let pattern = regex::Regex::new(pattern).unwrap();
+        let (isoforms, unmatched) = regex_isoforms(&bed, &pattern);
+        if !unmatched.is_empty() {
+            if args.strict {
+                log::error!("Transcript {} does not match --gene-regex.", unmatched[0]);
+                std::process::exit(1);
             }
+            log::warn!(
+                "{} transcripts do not match --gene-regex and are their own gene",
+                unmatched.len()
+            );
         }
-    } else {
-        &bedline.name
-    };
+        imap = isoforms;
+    } else if args.gene_from_name2 {
+        let (isoforms, missing) = name2_isoforms(&bed);
+        if !missing.is_empty() {
+            if args.strict {
+                log::error!("Transcript {} has no name2 column.", missing[0]);
+                std::process::exit(1);
+            }
+            log::warn!(
+                "{} transcripts have no name2 column and are their own gene",
+                missing.len()
+            );
+        }
+        imap = isoforms;
+    } else if args.isoforms.is_none() && !args.no_gene {
+        log::info!("No isoforms file given, each transcript is its own gene");
+        imap = identity_isoforms(&bed);
+    }
 
-    let fcodon = first_codon(bedline)
-        .unwrap_or_else(|| panic!("No start codon found for {}.", bedline.name));
-    let lcodon = last_codon(bedline).unwrap_or_else(|| {
-        panic!("No stop codon found for {}.", bedline.name);
-    });
-    // let first_utr_end = bedline.cds_start;
-    // let last_utr_start = bedline.cds_end;
-    let frames = bedline.get_frames();
+    if args.report_unmapped_categories {
+        let report = unmapped_categories(&bed, &imap, &args.categories, args.category_match);
+        let total: usize = report.iter().map(|(_, count)| count).sum();
 
-    let cds_end: u32 = if bedline.strand == "+" && codon_complete(&lcodon) {
-        move_pos(bedline, lcodon.end, -3)
-    } else {
-        bedline.cds_end
-    };
+        // stderr, like the --validate-only report
+        eprintln!("Unmapped transcripts: {}", total);
+        for (category, count) in report {
+            eprintln!("  {}: {}", category, count);
+        }
+        return;
+    }
 
-    let cds_start = if bedline.strand == "-" && codon_complete(&fcodon) {
-        move_pos(bedline, fcodon.start, 3)
+    let unmapped = if imap.is_empty() {
+        Vec::new()
     } else {
-        bedline.cds_start
+        drop_unmapped(&mut bed, &imap)
     };
+    if let Some(path) = &args.dump_unmapped {
+        write_names(path, &unmapped).unwrap_or_else(|e| {
+            log::error!("Error writing unmapped transcripts to {:?}: {}", path, e);
+            std::process::exit(1);
+        });
+        if !unmapped.is_empty() && !args.allow_missing_isoforms {
+            log::error!(
+                "{} transcripts not found in isoforms file, listed in {:?} (use --allow-missing-isoforms to skip them).",
+                unmapped.len(),
+                path
+            );
+            std::process::exit(1);
+        }
+    }
+    for name in &unmapped {
+        if !args.allow_missing_isoforms {
+            log::error!(
+                "Transcript {} not found in isoforms file (use --allow-missing-isoforms to skip it).",
+                name
+            );
+            std::process::exit(1);
+        }
+        log::warn!(
+            "Transcript {} not found in isoforms file, skipping it.",
+            name
+        );
+    }
 
-    build_gff_line(
-        bedline,
-        gene,
-        "transcript",
-        bedline.tx_start,
-        bedline.tx_end,
-        3,
-        -1,
-        &mut result,
-    );
-
-    for i in 0..bedline.exon_count as usize {
-        build_gff_line(
-            bedline,
-            gene,
-            "exon",
-            bedline.exon_start[i],
-            bedline.exon_end[i],
-            3,
-            i as i16,
-            &mut result,
+    let filtered = filter_exons(&mut bed, args.min_exons, args.max_exons);
+    if filtered > 0 {
+        log::info!("Filtered {} transcripts by exon count", filtered);
+    }
+
+    if let Some(max) = args.max_intron {
+        let dropped = filter_introns(&mut bed, max);
+        if args.strict && !dropped.is_empty() {
+            let (name, intron) = &dropped[0];
+            log::error!(
+                "Transcript {} has a {} bp intron, over --max-intron {}.",
+                name,
+                intron,
+                max
+            );
+            std::process::exit(1);
+        }
+        for (name, intron) in &dropped {
+            log::warn!("Skipping {}: {} bp intron over --max-intron", name, intron);
+        }
+    }
+
+    if let Some(min) = args.min_cds_length {
+        let dropped = filter_cds_length(&mut bed, min);
+        for (name, cds) in &dropped {
+            log::debug!("Skipping {}: {} bp CDS under --min-cds-length", name, cds);
+        }
+        log::info!(
+            "Skipped {} transcripts with a CDS shorter than {} bp",
+            dropped.len(),
+            min
         );
-        if cds_start < cds_end {
-            write_features(
-                i,
-                bedline,
-                gene,
-                // first_utr_end,
-                cds_start,
-                cds_end,
-                // last_utr_start,
-                frames[i] as u32,
-                &mut result,
+    }
+
+    if let Some(path) = &args.rename_transcripts {
+        let renames = read_file(path, "transcript renaming file").unwrap_or_else(|e| {
+            log::error!("{}", e);
+            std::process::exit(1);
+        });
+        rename_transcripts(&mut bed, &mut imap, &parallel_hash(&renames));
+    }
+
+    let out_of_bounds = args
+        .chrom_sizes
+        .as_ref()
+        .map(|_| check_bounds(&bed, &config.chrom_sizes));
+    if args.strict && out_of_bounds.is_some_and(|count| count > 0) {
+        log::error!("Transcripts extend beyond chromosome ends.");
+        std::process::exit(1);
+    }
+
+    if let Some(path) = &args.chrom_map {
+        let map = read_file(path, "chromosome map file").unwrap_or_else(|e| {
+            log::error!("{}", e);
+            std::process::exit(1);
+        });
+        for chrom in map_chroms(&mut bed, &parallel_hash(&map)) {
+            if args.strict {
+                log::error!("Chromosome {} not found in chromosome map.", chrom);
+                std::process::exit(1);
+            }
+            log::warn!(
+                "Chromosome {} not found in chromosome map, keeping it.",
+                chrom
             );
         }
     }
 
-    if bedline.strand != "-" {
-        if codon_complete(&fcodon) {
-            write_codon(bedline, gene, "start_codon", fcodon, &mut result);
+    let mut unstranded = 0;
+    for record in bed.iter_mut().filter(|record| record.strand == ".") {
+        unstranded += 1;
+        if let Some(strand) = &args.default_strand {
+            record.strand = strand.clone();
         }
-        if codon_complete(&lcodon) {
-            write_codon(bedline, gene, "stop_codon", lcodon, &mut result);
+    }
+    if unstranded > 0 {
+        match args.default_strand.as_deref() {
+            Some(".") | None => log::warn!(
+                "{} transcripts have strand '.', writing them without CDS or codons",
+                unstranded
+            ),
+            Some(strand) => log::info!(
+                "Set strand of {} unstranded transcripts to {}",
+                unstranded,
+                strand
+            ),
         }
-    } else {
-        if codon_complete(&lcodon) {
-            write_codon(bedline, gene, "start_codon", lcodon, &mut result);
+    }
+
+    let split = split_genes(&bed, &mut imap);
+    if args.strict {
+        if let Some((gene, names)) = split.first() {
+            log::error!(
+                "Gene {} has transcripts on {} chromosomes or strands.",
+                gene,
+                names.len()
+            );
+            std::process::exit(1);
         }
-        if codon_complete(&fcodon) {
-            write_codon(bedline, gene, "stop_codon", fcodon, &mut result);
+    }
+    for (gene, names) in &split {
+        log::warn!(
+            "Gene {} has transcripts on several chromosomes or strands, split into {}",
+            gene,
+            names.join(", ")
+        );
+        if let Some(name) = config.gene_names.get(gene).cloned() {
+            for split_name in names {
+                config.gene_names.insert(split_name.clone(), name.clone());
+            }
         }
     }
 
-    Ok(result)
-}
+    if let Some(mode) = args.canonical {
+        let dropped = canonical_isoforms(&mut bed, &imap, mode);
+        log::info!("Kept one isoform per gene, dropped {} transcripts", dropped);
+    }
 
-fn move_pos(record: &BedRecord, pos: u32, dist: i32) -> u32 {
-    let mut pos = pos;
-    assert!(record.tx_start <= pos && pos <= record.tx_end);
+    if args.assume_sorted {
+        let unsorted = unsorted_records(&bed, config.sort, &config.seqid_order);
+        if let Some(&idx) = unsorted.first() {
+            log::warn!(
+                "{} records are out of order despite --assume-sorted, first {} at {}:{}; keeping the input order",
+                unsorted.len(),
+                bed[idx].name,
+                bed[idx].chrom,
+                bed[idx].tx_start
+            );
+        }
+    } else {
+        sort_records(&mut bed, config.sort, &config.seqid_order);
+    }
+    if (config.sort == SortOrder::None || args.assume_sorted) && args.output_dir.is_some() {
+        // per-chromosome files need each chromosome in one run
+        bed.sort_by(|a, b| a.chrom.cmp(&b.chrom));
+    }
+    if let Some(max) = args.max_records {
+        let dropped = head_records(&mut bed, max);
+        if dropped > 0 {
+            log::info!(
+                "Reached --max-records {}, skipping the remaining {} transcripts",
+                max,
+                dropped
+            );
+        }
+    }
+    let genes = gene_lines(&bed, &imap, &config);
 
-    let mut exon_index = record
-        .exon_start
+    let incomplete = bed
         .iter()
-        .zip(record.exon_end.iter())
-        .position(|(start, end)| pos >= *start && pos <= *end)
-        .unwrap_or_else(|| {
-            let message = format!("Position {} not in exons.", pos);
-            panic!("{}", message);
-        }) as i16;
-
-    let mut steps = dist.abs();
-    let direction = if dist >= 0 { 1 } else { -1 };
-
-    while steps > 0 {
-        let (exon_start, exon_end) = (
-            record.exon_start[exon_index as usize],
-            record.exon_end[exon_index as usize],
+        .filter_map(|record| {
+            let missing = missing_codons(record);
+            (!missing.is_empty()).then(|| format!("{}\t{}", record.name, missing.join(",")))
+        })
+        .collect::<Vec<_>>();
+    if !incomplete.is_empty() {
+        log::warn!(
+            "{} coding transcripts miss a start or stop codon",
+            incomplete.len()
         );
+    }
+    if let Some(path) = &args.incomplete_cds {
+        write_names(path, &incomplete).unwrap_or_else(|e| {
+            log::error!("Error writing incomplete transcripts to {:?}: {}", path, e);
+            std::process::exit(1);
+        });
+    }
 
-        if pos >= exon_start && pos <= exon_end {
-            pos += direction as u32;
-            steps -= 1;
-        } else if direction >= 0 {
-            exon_index += 1;
-            if (exon_index as usize) < record.exon_count as usize {
-                pos = record.exon_start[exon_index as usize];
+    if args.cds_frame_check {
+        let mut inconsistent = 0;
+        for record in bed.iter().filter(|r| r.cds_start < r.cds_end) {
+            let problems = record.frame_problems();
+            if !problems.is_empty() {
+                inconsistent += 1;
             }
-        } else {
-            exon_index -= 1;
-            if exon_index >= 0 {
-                pos = record.exon_end[exon_index as usize] - 1;
-                steps -= 1;
+            for problem in problems {
+                log::warn!("{}", problem);
             }
         }
+        log::info!(
+            "Frame check: {} coding transcripts with inconsistent frames",
+            inconsistent
+        );
     }
-    if steps > 0 {
-        panic!("can't move {} by {}", pos, dist);
+
+    let mut stats = ConversionStats::default();
+    let mut outputs = Vec::new();
+    if let Some(dir) = &args.output_dir {
+        let written = write_by_chrom(
+            dir,
+            &bed,
+            &imap,
+            &genes,
+            &config,
+            args.codec(),
+            args.buffer_size,
+        )
+        .unwrap_or_else(|e| {
+            log::error!("{}", e);
+            std::process::exit(1);
+        });
+        for (path, chrom_stats) in written {
+            stats += chrom_stats;
+            outputs.push(path);
+        }
+    } else if let (Some(coding_out), Some(noncoding_out)) = (&args.coding_out, &args.noncoding_out)
+    {
+        let (coding, noncoding): (Vec<_>, Vec<_>) = bed
+            .into_iter()
+            .partition(|record| record.cds_start < record.cds_end);
+
+        for (path, records) in [(coding_out, coding), (noncoding_out, noncoding)] {
+            let path = &args.output_path(path);
+            // a gene with transcripts in both files gets a line in each
+            let genes = clip_genes(&records, &imap, &genes);
+            stats += write_output(path, &records, &imap, &genes, &args, &config);
+            outputs.push(path.clone());
+        }
+    } else {
+        let output = &args.output_path(args.output.as_ref().unwrap());
+        stats += write_output(output, &bed, &imap, &genes, &args, &config);
+        outputs.push(output.clone());
+    }
+
+    log::info!("Converted {}", stats);
+
+    if args.validate_gff {
+        let mut failed = false;
+
+        for path in &outputs {
+            if path.as_os_str() == STDOUT {
+                log::warn!("Skipping validation of output written to stdout");
+                continue;
+            }
+
+            let problems = open_gff(path, args.codec() != Codec::None)
+                .and_then(validate_gff)
+                .unwrap_or_else(|e| {
+                    log::error!("{}", path_error(e, "read", path));
+                    std::process::exit(1);
+                });
+
+            if !problems.is_empty() {
+                failed = true;
+                log::error!("{} problems found in {}", problems.len(), path.display());
+                for problem in problems.iter().take(MAX_REPORTED_PROBLEMS) {
+                    log::error!("{}", problem);
+                }
+            }
+        }
+
+        if failed {
+            std::process::exit(1);
+        }
+        log::info!("GFF validation passed");
+    }
+
+    if args.skip_malformed {
+        log::info!("Skipped {} malformed lines", skipped);
+    }
+
+    if args.allow_missing_isoforms {
+        log::info!("Skipped {} unmapped transcripts", unmapped.len());
+    }
+
+    if let Some(count) = out_of_bounds {
+        log::info!("Transcripts beyond chromosome ends: {}", count);
+    }
+
+    if let Some(path) = &args.manifest {
+        let inputs = args.bed.iter().chain(&args.isoforms).collect::<Vec<_>>();
+        write_manifest(
+            path,
+            &inputs,
+            &outputs,
+            &stats,
+            skipped,
+            unmapped.len(),
+            start.elapsed().as_secs_f64(),
+        )
+        .unwrap_or_else(|e| {
+            log::error!("Error writing manifest to {:?}: {}", path, e);
+            std::process::exit(1);
+        });
     }
-    pos
+
+    let peak_mem = (max_mem_usage_mb() - bmem).max(0.0);
+    log::info!("Memory usage: {} MB", peak_mem);
+    log::info!("Elapsed: {:.4?} secs", start.elapsed().as_secs_f32())
+}
+
+/// Writes the header, unless appending to a non-empty file, and the records
+/// to `path`, exiting with an error naming the file if any step fails.
+fn write_output(
+    path: &PathBuf,
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    genes: &HashMap<String, GffRecord>,
+    args: &Cli,
+    config: &Config,
+) -> ConversionStats {
+    // appended outputs keep the header they already have; gene lines are
+    // deduplicated within this run only
+    let header =
+        !args.append || std::fs::metadata(path).map_or(true, |metadata| metadata.len() == 0);
+    let written =
+        create_writer(path, args.codec(), args.buffer_size, args.append).and_then(|writer| {
+            let mut writer = config.line_ending.wrap(writer);
+            if header {
+                comments(&mut writer, config);
+            }
+            let stats = write_gff(&mut writer, records, isoforms, genes, config)
+                .and_then(|stats| writer.flush().map(|_| stats))
+                .map_err(|e| path_error(e, "write", path))?;
+            Ok(stats)
+        });
+
+    written.unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(1);
+    })
 }
@@ -1,53 +1,610 @@
-use crate::bed::BedRecord;
+use crate::bed::{BedRecord, NameSource, ParseError};
+use crate::config::{Canonical, CategoryMatch, Codec, Config, Feature, Format};
+use crate::gff::{ConversionStats, GffRecord};
 
 use chrono::Datelike;
+use log::{LevelFilter, Log, Metadata, Record};
 
 use colored::Colorize;
 
 use indoc::indoc;
 
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
 use rayon::prelude::*;
 
-use std::collections::HashMap;
+use regex::Regex;
+
+use serde_json::json;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-const SOURCE: &str = "bed2gff";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const GFF3: &str = "##gff-version 3";
 const REPOSITORY: &str = "github.com/alejandrogzi/bed2gff";
+const GZ_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+pub type GeneTrack = HashMap<String, (String, u32, u32, String)>;
+
+/// Reads a BED file, also accepting BED6 lines if `bed6` is set and
+/// splitting fields on any whitespace if `whitespace` is set; transcript
+/// names come from `names`. A malformed line aborts the run unless
+/// `skip_malformed` is set, in which case it is logged and counted in the
+/// returned total.
+pub fn bed_reader(
+    file: &PathBuf,
+    skip_malformed: bool,
+    bed6: bool,
+    whitespace: bool,
+    names: &NameSource,
+) -> (Vec<BedRecord>, usize) {
+    let bed = read_file(file, "BED file").unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(1);
+    });
+    let parse = match (bed6, whitespace) {
+        (false, false) => BedRecord::parse,
+        (false, true) => BedRecord::parse_whitespace,
+        (true, false) => BedRecord::parse_bed6,
+        (true, true) => BedRecord::parse_bed6_whitespace,
+    };
+    let (records, malformed) = parse_lines(&bed, |line| {
+        let mut record = parse(line)?;
+        if let Some(name) = names.extract(line, whitespace)? {
+            record.name = name;
+        }
+        Ok(record)
+    });
+
+    for (n, line, e) in &malformed {
+        if skip_malformed {
+            log::warn!("Skipping {} line {}: {} {:?}", file.display(), n, e, line);
+        } else {
+            log::error!(
+                "{} line {}: {} {:?} (use --skip-malformed to ignore it)",
+                file.display(),
+                n,
+                e,
+                line
+            );
+            std::process::exit(1);
+        }
+    }
+
+    (records, malformed.len())
+}
+
+/// Reads the isoforms file with `isoform_columns`; a file that maps no
+/// transcript is an error.
+pub fn get_isoforms(
+    file: &str,
+    gene_col: usize,
+    tx_col: usize,
+    priority_col: Option<usize>,
+    name_col: Option<usize>,
+    delim: Option<char>,
+) -> Result<Isoforms, String> {
+    let (pairs, names) = isoform_columns(file, gene_col, tx_col, priority_col, name_col, delim)?;
+    if pairs.is_empty() {
+        return Err(
+            "BED file could not be converted. Please check your isoforms file.".to_string(),
+        );
+    }
+    Ok((pairs, names))
+}
 
-pub fn bed_reader(file: &PathBuf) -> Vec<BedRecord> {
-    let bed = reader(file).unwrap();
-    let records = parallel_parse(&bed).unwrap();
+/// One-to-one isoforms map used when no isoforms file is given: every
+/// transcript is the only isoform of a gene sharing its name.
+pub fn identity_isoforms(records: &[BedRecord]) -> HashMap<String, String> {
     records
+        .par_iter()
+        .map(|record| (record.name.clone(), record.name.clone()))
+        .collect()
 }
 
-pub fn get_isoforms(file: &String) -> HashMap<String, String> {
-    let pairs = parallel_hash_rev(file);
-    // let rev_pairs = parallel_hash(&file);
+/// Isoforms map taking each gene from the first capture group of `pattern`
+/// matched against the transcript name. Names that don't match are their
+/// own gene and are also returned, in input order.
+pub fn regex_isoforms(
+    records: &[BedRecord],
+    pattern: &Regex,
+) -> (HashMap<String, String>, Vec<String>) {
+    let mut isoforms = HashMap::new();
+    let mut unmatched = Vec::new();
 
-    if pairs.len() == 0 {
-        println!(
-            "{} {}",
-            "Fail:".bright_red().bold(),
-            "BED file could not be converted. Please check your isoforms file."
-        );
-        std::process::exit(1);
+    for record in records {
+        let gene = match pattern.captures(&record.name).and_then(|caps| caps.get(1)) {
+            Some(gene) => gene.as_str().to_string(),
+            None => {
+                unmatched.push(record.name.clone());
+                record.name.clone()
+            }
+        };
+        isoforms.insert(record.name.clone(), gene);
+    }
+
+    (isoforms, unmatched)
+}
+
+/// Isoforms map taking each gene from the name2 column of BED15 /
+/// bigGenePred records. Transcripts without one are their own gene and are
+/// also returned, in input order.
+pub fn name2_isoforms(records: &[BedRecord]) -> (HashMap<String, String>, Vec<String>) {
+    let mut isoforms = HashMap::new();
+    let mut missing = Vec::new();
+
+    for record in records {
+        let gene = match &record.name2 {
+            Some(gene) => gene.clone(),
+            None => {
+                missing.push(record.name.clone());
+                record.name.clone()
+            }
+        };
+        isoforms.insert(record.name.clone(), gene);
+    }
+
+    (isoforms, missing)
+}
+
+/// Transcript -> gene map and gene -> symbol map of an isoforms file
+pub type Isoforms = (HashMap<String, String>, HashMap<String, String>);
+
+/// Reads a transcript -> gene map from the 1-based `gene_col` and `tx_col`
+/// columns of an isoforms file, split on `delim` or on whitespace if none
+/// is given. Blank lines are skipped and CRLF endings trimmed; lines missing
+/// a column are an error naming the line.
+///
+/// A transcript listed with several genes keeps the one with the highest
+/// number in `priority_col`, or the last one on ties and without priorities;
+/// each conflict is logged.
+///
+/// Gene symbols come from the optional `name_col`, in the same pass; lines
+/// too short to have it give their gene no symbol.
+pub fn isoform_columns(
+    s: &str,
+    gene_col: usize,
+    tx_col: usize,
+    priority_col: Option<usize>,
+    name_col: Option<usize>,
+    delim: Option<char>,
+) -> Result<Isoforms, String> {
+    let lines = s
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    let columns = gene_col.max(tx_col).max(priority_col.unwrap_or(0));
+
+    let entries = lines
+        .par_iter()
+        .map(|(idx, line)| {
+            let fields = match delim {
+                Some(delim) => line.split(delim).map(str::trim).collect::<Vec<_>>(),
+                None => line.split_whitespace().collect::<Vec<_>>(),
+            };
+            if fields.len() < columns {
+                return Err(format!(
+                    "isoforms line {}: expected at least {} columns, found {}",
+                    idx + 1,
+                    columns,
+                    fields.len()
+                ));
+            }
+
+            let priority = match priority_col {
+                Some(col) => fields[col - 1].parse::<f64>().map_err(|_| {
+                    format!(
+                        "isoforms line {}: invalid priority {:?}",
+                        idx + 1,
+                        fields[col - 1]
+                    )
+                })?,
+                None => 0.0,
+            };
+            let name = name_col.and_then(|col| fields.get(col - 1).copied());
+            Ok((fields[tx_col - 1], fields[gene_col - 1], priority, name))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut names = HashMap::new();
+    let mut isoforms: HashMap<String, (String, f64)> = HashMap::with_capacity(entries.len());
+    for (tx, gene, priority, name) in entries {
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+            names.insert(gene.to_string(), name.to_string());
+        }
+        match isoforms.get_mut(tx) {
+            Some((kept, kept_priority)) if kept != gene => {
+                if priority >= *kept_priority {
+                    log::warn!("{}: gene {} overrides {}", tx, gene, kept);
+                    *kept = gene.to_string();
+                    *kept_priority = priority;
+                } else {
+                    log::warn!("{}: gene {} keeps priority over {}", tx, kept, gene);
+                }
+            }
+            Some((_, kept_priority)) => *kept_priority = kept_priority.max(priority),
+            None => {
+                isoforms.insert(tx.to_string(), (gene.to_string(), priority));
+            }
+        }
+    }
+
+    let isoforms = isoforms
+        .into_iter()
+        .map(|(tx, (gene, _))| (tx, gene))
+        .collect();
+    Ok((isoforms, names))
+}
+
+pub fn get_chrom_sizes(file: &str) -> HashMap<String, u32> {
+    file.par_lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let chrom = words.next()?;
+            let size = words.next()?.parse::<u32>().ok()?;
+            Some((chrom.to_owned(), size))
+        })
+        .collect()
+}
+
+/// Ranks the chromosomes of a one-per-line order file; repeated names keep
+/// their first position
+pub fn seqid_ranks(file: &str) -> HashMap<String, usize> {
+    let mut ranks = HashMap::new();
+    for chrom in file
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+    {
+        let rank = ranks.len();
+        ranks.entry(chrom.to_owned()).or_insert(rank);
+    }
+    ranks
+}
+
+/// Warns about every transcript ending past its chromosome length and
+/// returns how many were found. Chromosomes absent from `sizes` are skipped.
+pub fn check_bounds(records: &[BedRecord], sizes: &HashMap<String, u32>) -> usize {
+    records
+        .par_iter()
+        .filter(|record| match sizes.get(&record.chrom) {
+            Some(&size) if record.tx_end > size => {
+                log::warn!(
+                    "Transcript {} extends {} bp beyond the end of {} ({} > {}).",
+                    record.name,
+                    record.tx_end - size,
+                    record.chrom,
+                    record.tx_end,
+                    size
+                );
+                true
+            }
+            _ => false,
+        })
+        .count()
+}
+
+/// Gives every repeated transcript name a `.1`, `.2`, ... suffix, keeping
+/// the first occurrence as is. Returns the index of each renamed record
+/// and its original name, in input order.
+pub fn dedup_names(records: &mut [BedRecord]) -> Vec<(usize, String)> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut taken = records
+        .iter()
+        .map(|record| record.name.clone())
+        .collect::<HashSet<_>>();
+    let mut renamed = Vec::new();
+
+    for (idx, record) in records.iter_mut().enumerate() {
+        let count = seen.entry(record.name.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            continue;
+        }
+
+        let mut suffix = *count - 1;
+        let mut name = format!("{}.{}", record.name, suffix);
+        while taken.contains(&name) {
+            suffix += 1;
+            name = format!("{}.{}", record.name, suffix);
+        }
+        *count = suffix + 1;
+        taken.insert(name.clone());
+        renamed.push((idx, std::mem::replace(&mut record.name, name)));
+    }
+
+    renamed
+}
+
+/// Removes records missing from the isoforms map and returns their names in
+/// input order.
+pub fn drop_unmapped(
+    records: &mut Vec<BedRecord>,
+    isoforms: &HashMap<String, String>,
+) -> Vec<String> {
+    let mut unmapped = Vec::new();
+    records.retain(|record| {
+        let mapped = isoforms.contains_key(&record.name);
+        if !mapped {
+            unmapped.push(record.name.clone());
+        }
+        mapped
+    });
+    unmapped
+}
+
+/// Writes one name per line to `path`.
+pub fn write_names(path: &PathBuf, names: &[String]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for name in names {
+        writeln!(file, "{}", name)?;
+    }
+    file.flush()
+}
+
+/// Writes a JSON summary of a run to `path`: tool version, input and output
+/// paths, feature counts, malformed lines and unmapped transcripts skipped,
+/// and wall-clock seconds.
+pub fn write_manifest(
+    path: &PathBuf,
+    inputs: &[&PathBuf],
+    outputs: &[PathBuf],
+    stats: &ConversionStats,
+    malformed: usize,
+    unmapped: usize,
+    elapsed: f64,
+) -> io::Result<()> {
+    let manifest = json!({
+        "version": VERSION,
+        "inputs": inputs,
+        "outputs": outputs,
+        "counts": {
+            "genes": stats.genes,
+            "transcripts": stats.transcripts,
+            "exons": stats.exons,
+            "cds": stats.cds,
+            "utr": stats.utr,
+            "skipped": stats.skipped,
+        },
+        "malformed": malformed,
+        "unmapped": unmapped,
+        "elapsed_secs": elapsed,
+    });
+
+    let mut file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut file, &manifest)?;
+    writeln!(file)?;
+    file.flush()
+}
+
+/// Drops records with fewer than `min` or more than `max` exons and returns
+/// how many were dropped.
+pub fn filter_exons(records: &mut Vec<BedRecord>, min: Option<u16>, max: Option<u16>) -> usize {
+    let before = records.len();
+    records.retain(|record| {
+        min.is_none_or(|min| record.exon_count >= min)
+            && max.is_none_or(|max| record.exon_count <= max)
+    });
+    before - records.len()
+}
+
+/// Drops records with an intron longer than `max` and returns their names
+/// with that intron length, in input order.
+pub fn filter_introns(records: &mut Vec<BedRecord>, max: u32) -> Vec<(String, u32)> {
+    let mut dropped = Vec::new();
+    records.retain(|record| {
+        let intron = record.longest_intron();
+        if intron > max {
+            dropped.push((record.name.clone(), intron));
+        }
+        intron <= max
+    });
+    dropped
+}
+
+/// Keeps one isoform per gene, the longest by `mode`, breaking ties with
+/// the smallest transcript name. Transcripts missing from `isoforms` are
+/// their own gene. Returns the number of dropped records.
+pub fn canonical_isoforms(
+    records: &mut Vec<BedRecord>,
+    isoforms: &HashMap<String, String>,
+    mode: Canonical,
+) -> usize {
+    let length = |record: &BedRecord| match mode {
+        Canonical::LongestCds => record.cds_length(),
+        Canonical::LongestTx => record.exon_length(),
+    };
+
+    let mut best: HashMap<&str, &BedRecord> = HashMap::new();
+    for record in records.iter() {
+        let gene = isoforms.get(&record.name).unwrap_or(&record.name);
+        best.entry(gene)
+            .and_modify(|kept| {
+                let (a, b) = (length(record), length(kept));
+                if a > b || (a == b && record.name < kept.name) {
+                    *kept = record;
+                }
+            })
+            .or_insert(record);
+    }
+
+    let keep = best
+        .into_values()
+        .map(|record| record.name.clone())
+        .collect::<HashSet<_>>();
+    let before = records.len();
+    records.retain(|record| keep.contains(&record.name));
+    before - records.len()
+}
+
+/// Keeps the first `max` records, those written first once sorted, and
+/// returns how many were dropped. Runs on the fully read and sorted input.
+pub fn head_records(records: &mut Vec<BedRecord>, max: usize) -> usize {
+    let dropped = records.len().saturating_sub(max);
+    records.truncate(max);
+    dropped
+}
+
+/// Drops coding records whose summed CDS is shorter than `min` and returns
+/// their names with that length, in input order. Noncoding records are
+/// kept.
+pub fn filter_cds_length(records: &mut Vec<BedRecord>, min: u32) -> Vec<(String, u32)> {
+    let mut dropped = Vec::new();
+    records.retain(|record| {
+        let cds = record.cds_length();
+        let short = record.cds_start < record.cds_end && cds < min;
+        if short {
+            dropped.push((record.name.clone(), cds));
+        }
+        !short
+    });
+    dropped
+}
+
+/// Renames chromosomes through `map`, leaving the ones it lacks unchanged.
+/// Returns those unmapped chromosomes, sorted.
+pub fn map_chroms(records: &mut [BedRecord], map: &HashMap<String, String>) -> Vec<String> {
+    let mut unmapped = HashSet::new();
+    for record in records.iter_mut() {
+        match map.get(&record.chrom) {
+            Some(chrom) => record.chrom = chrom.clone(),
+            None => {
+                unmapped.insert(record.chrom.clone());
+            }
+        }
+    }
+
+    let mut unmapped = unmapped.into_iter().collect::<Vec<_>>();
+    unmapped.sort();
+    unmapped
+}
+
+/// Renames transcripts after their genes have been resolved: records take
+/// their new names and the isoforms map is re-keyed so lookups keep
+/// pointing to the gene of the original name.
+pub fn rename_transcripts(
+    records: &mut [BedRecord],
+    isoforms: &mut HashMap<String, String>,
+    names: &HashMap<String, String>,
+) {
+    records.par_iter_mut().for_each(|record| {
+        if let Some(name) = names.get(&record.name) {
+            record.name = name.clone();
+        }
+    });
+
+    *isoforms = isoforms
+        .drain()
+        .map(|(tx, gene)| match names.get(&tx) {
+            Some(name) => (name.clone(), gene),
+            None => (tx, gene),
+        })
+        .collect();
+}
+
+/// Counts transcripts absent from the isoforms map per category tag. Each
+/// name lands in the first matching tag; the last entry ("other") holds
+/// unmapped names matching none of them.
+pub fn unmapped_categories(
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    categories: &[String],
+    by: CategoryMatch,
+) -> Vec<(String, usize)> {
+    let mut counts = vec![0; categories.len() + 1];
+
+    for record in records {
+        if isoforms.contains_key(&record.name) {
+            continue;
+        }
+
+        let idx = categories
+            .iter()
+            .position(|category| by.matches(&record.name, category))
+            .unwrap_or(categories.len());
+        counts[idx] += 1;
+    }
+
+    categories
+        .iter()
+        .cloned()
+        .chain(std::iter::once("other".to_string()))
+        .zip(counts)
+        .collect()
+}
+
+/// Problems found in parsed records before conversion: CDS out of bounds,
+/// duplicate names and, when an isoforms map is given, transcripts missing
+/// from it. Returned in input order.
+pub fn input_problems(records: &[BedRecord], isoforms: &HashMap<String, String>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut seen = HashSet::new();
+
+    for record in records {
+        if let Err(e) = record.validate() {
+            problems.push(e.to_string());
+        }
+        if !seen.insert(record.name.as_str()) {
+            problems.push(format!("{}: duplicate transcript name", record.name));
+        }
+        if !isoforms.is_empty() && !isoforms.contains_key(&record.name) {
+            problems.push(format!("{}: not found in isoforms file", record.name));
+        }
     }
-    // (pairs, rev_pairs)
-    pairs
+
+    problems
+}
+
+/// Prefixes an I/O error with what failed on which file, e.g.
+/// "could not open isoforms file 'iso.txt': No such file or directory".
+pub fn path_error(e: io::Error, action: &str, path: &Path) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        format!("could not {} '{}': {}", action, path.display(), e),
+    )
+}
+
+/// Like `reader`, with errors naming the file and its role `what`.
+pub fn read_file(path: &PathBuf, what: &str) -> io::Result<String> {
+    reader(path).map_err(|e| path_error(e, &format!("open {}", what), path))
 }
 
+/// Reads a whole input file into memory, transparently decompressing it
+/// when it starts with the gzip magic bytes. Use `open_input` to stream it
+/// instead.
 pub fn reader(file: &PathBuf) -> io::Result<String> {
-    let mut file = File::open(file)?;
     let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+    open_input(file)?.read_to_string(&mut contents)?;
     Ok(contents)
 }
 
-pub fn parallel_hash<'a>(s: &'a str) -> HashMap<String, String> {
+/// Opens an input file for buffered reading, or stdin when the path is `-`;
+/// gzip (and bgzip) input is detected by its magic bytes and decompressed
+/// on the fly.
+pub fn open_input(path: &PathBuf) -> io::Result<Box<dyn BufRead>> {
+    if path.as_os_str() == STDIN {
+        decompress(BufReader::new(io::stdin()))
+    } else {
+        decompress(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Wraps `reader` in a gzip decoder if its content starts with the gzip
+/// magic bytes.
+pub fn decompress<R: BufRead + 'static>(mut reader: R) -> io::Result<Box<dyn BufRead>> {
+    if reader.fill_buf()?.starts_with(&GZ_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+pub fn parallel_hash(s: &str) -> HashMap<String, String> {
     s.par_lines()
         .filter_map(|line| {
             let mut words = line.split_whitespace();
@@ -62,7 +619,38 @@ pub fn parallel_hash<'a>(s: &'a str) -> HashMap<String, String> {
         .collect()
 }
 
-pub fn parallel_hash_rev<'a>(s: &'a str) -> HashMap<String, String> {
+/// Parses `--xref` lines of a gene or transcript name, comma-separated
+/// cross-references and an optional attribute (Dbxref if absent), e.g.
+/// `GENE1<TAB>GO:0005634<TAB>Ontology_term`. Values of repeated keys and
+/// attributes accumulate in file order.
+pub fn xref_map(s: &str) -> HashMap<String, Vec<(String, Vec<String>)>> {
+    let mut xrefs: HashMap<String, Vec<(String, Vec<String>)>> = HashMap::new();
+    for line in s.lines() {
+        let mut words = line.split_whitespace();
+        let (Some(key), Some(values)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let attr = words.next().unwrap_or("Dbxref");
+
+        let attrs = xrefs.entry(key.to_owned()).or_default();
+        let idx = match attrs.iter().position(|(name, _)| name == attr) {
+            Some(idx) => idx,
+            None => {
+                attrs.push((attr.to_owned(), Vec::new()));
+                attrs.len() - 1
+            }
+        };
+        attrs[idx].1.extend(
+            values
+                .split(',')
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned),
+        );
+    }
+    xrefs
+}
+
+pub fn parallel_hash_rev(s: &str) -> HashMap<String, String> {
     s.par_lines()
         .filter_map(|line| {
             let mut words = line.split_whitespace();
@@ -76,91 +664,344 @@ pub fn parallel_hash_rev<'a>(s: &'a str) -> HashMap<String, String> {
         .collect()
 }
 
-pub fn parallel_parse<'a>(s: &'a str) -> Result<Vec<BedRecord>, &'static str> {
-    let records: Result<Vec<BedRecord>, &'static str> =
-        s.par_lines().map(|line| BedRecord::parse(line)).collect();
+pub fn parallel_parse(s: &str) -> Result<Vec<BedRecord>, ParseError> {
+    s.par_lines()
+        .filter(|line| !is_header(line))
+        .map(BedRecord::parse)
+        .collect()
+}
 
-    records
+/// UCSC `track`/`browser` lines and `#` comments, skipped when parsing.
+/// The keyword must be a whole word, so chromosomes like `trackX` parse.
+pub fn is_header(line: &str) -> bool {
+    line.starts_with('#')
+        || matches!(
+            line.split_whitespace().next(),
+            Some("track") | Some("browser")
+        )
 }
 
-pub fn custom_par_parse(
-    records: &Vec<BedRecord>,
-) -> Result<HashMap<String, (String, u32, u32, String)>, &'static str> {
+/// Parses every line in parallel, keeping the valid records and the 1-based
+/// number, content and error of each line that failed, both in input order.
+/// Header lines are skipped.
+pub fn parse_lines<F>(s: &str, parse: F) -> (Vec<BedRecord>, Vec<(usize, &str, ParseError)>)
+where
+    F: Fn(&str) -> Result<BedRecord, ParseError> + Sync,
+{
+    let lines = s
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !is_header(line))
+        .collect::<Vec<_>>();
+    let headers = s.lines().count() - lines.len();
+    if headers > 0 {
+        log::debug!("Skipped {} header lines", headers);
+    }
+    let parsed = lines
+        .par_iter()
+        .map(|(_, line)| parse(line))
+        .collect::<Vec<_>>();
+
+    let mut records = Vec::with_capacity(parsed.len());
+    let mut malformed = Vec::new();
+    for ((idx, line), result) in lines.into_iter().zip(parsed) {
+        match result {
+            Ok(record) => records.push(record),
+            Err(e) => malformed.push((idx + 1, line, e)),
+        }
+    }
+
+    (records, malformed)
+}
+
+pub fn custom_par_parse(records: &[BedRecord]) -> Result<GeneTrack, &'static str> {
     let gene_coordinates = records
         .into_par_iter()
-        .fold(
-            || HashMap::new(),
-            |mut acc: HashMap<String, (String, u32, u32, String)>, record| {
-                acc.entry(record.name.clone()).or_insert((
-                    record.chrom.clone(),
-                    record.tx_start,
-                    record.tx_end,
-                    record.strand.clone(),
-                ));
-                acc
-            },
-        )
-        .reduce(
-            || HashMap::new(),
-            |mut a: HashMap<String, (String, u32, u32, String)>, b| {
-                for (key, (chrom, start, end, strand)) in b {
-                    a.entry(key).or_insert((chrom, start, end, strand));
-                }
-                a
-            },
-        );
+        .fold(HashMap::new, |mut acc: GeneTrack, record| {
+            acc.entry(record.name.clone()).or_insert((
+                record.chrom.clone(),
+                record.tx_start,
+                record.tx_end,
+                record.strand.clone(),
+            ));
+            acc
+        })
+        .reduce(HashMap::new, |mut a: GeneTrack, b| {
+            for (key, (chrom, start, end, strand)) in b {
+                a.entry(key).or_insert((chrom, start, end, strand));
+            }
+            a
+        });
     Ok(gene_coordinates)
 }
 
+/// Splits genes whose transcripts lie on several chromosomes or strands
+/// into one gene per locus, named `<gene>.<chrom>` (plus `.plus`, `.minus`
+/// or `.unstranded` when strands differ), and remaps their transcripts in
+/// `isoforms`. Returns each split gene with its new names, sorted by gene.
+pub fn split_genes(
+    records: &[BedRecord],
+    isoforms: &mut HashMap<String, String>,
+) -> Vec<(String, Vec<String>)> {
+    let mut loci: HashMap<&str, BTreeSet<(&str, &str)>> = HashMap::new();
+    for record in records {
+        if let Some(gene) = isoforms.get(&record.name) {
+            loci.entry(gene)
+                .or_default()
+                .insert((&record.chrom, &record.strand));
+        }
+    }
+
+    let strand_name = |strand: &str| match strand {
+        "+" => "plus",
+        "-" => "minus",
+        _ => "unstranded",
+    };
+    let mut renames: HashMap<String, HashMap<(String, String), String>> = HashMap::new();
+    for (gene, loci) in loci.into_iter().filter(|(_, loci)| loci.len() > 1) {
+        let chroms = loci.iter().map(|(chrom, _)| chrom).collect::<HashSet<_>>();
+        let strands = loci
+            .iter()
+            .map(|(_, strand)| strand)
+            .collect::<HashSet<_>>();
+        let names = loci
+            .iter()
+            .map(|&(chrom, strand)| {
+                let mut name = gene.to_string();
+                if chroms.len() > 1 {
+                    name = format!("{}.{}", name, chrom);
+                }
+                if strands.len() > 1 {
+                    name = format!("{}.{}", name, strand_name(strand));
+                }
+                ((chrom.to_string(), strand.to_string()), name)
+            })
+            .collect();
+        renames.insert(gene.to_string(), names);
+    }
+
+    for record in records {
+        let Some(gene) = isoforms.get_mut(&record.name) else {
+            continue;
+        };
+        if let Some(names) = renames.get(gene.as_str()) {
+            *gene = names[&(record.chrom.clone(), record.strand.clone())].clone();
+        }
+    }
+
+    let mut split = renames
+        .into_iter()
+        .map(|(gene, names)| {
+            let mut names = names.into_values().collect::<Vec<_>>();
+            names.sort();
+            (gene, names)
+        })
+        .collect::<Vec<_>>();
+    split.sort();
+    split
+}
+
 pub fn combine_maps_par(
     isoforms: &HashMap<String, String>,
-    gene_track: &HashMap<String, (String, u32, u32, String)>,
-) -> Vec<(String, String, u32, u32, String, String, String)> {
-    let coords = isoforms
+    gene_track: &GeneTrack,
+    config: &Config,
+) -> Vec<GffRecord> {
+    gene_spans(isoforms, gene_track)
         .par_iter()
-        .fold(
-            || HashMap::new(),
-            |mut acc: HashMap<String, (String, u32, u32, String)>, (transcript, gene)| {
-                if let Some(&(ref chrom, start, end, ref strand)) = gene_track.get(transcript) {
-                    let entry = acc.entry(gene.clone()).or_insert((
-                        chrom.to_string(),
-                        start,
-                        end,
-                        strand.to_string(),
-                    ));
-                    entry.1 = entry.1.min(start); // Update min start
-                    entry.2 = entry.2.max(end); // Update max end
-                }
-                acc
-            },
-        )
-        .reduce(
-            || HashMap::new(),
-            |mut a, b| {
-                for (gene, (chrom, start, end, strand)) in b {
-                    let entry = a.entry(gene).or_insert((chrom, start, end, strand));
-                    entry.1 = entry.1.min(start); // Update min start
-                    entry.2 = entry.2.max(end); // Update max end
-                }
-                a
-            },
-        );
+        .map(|(gene, span)| gene_line(gene, span, None, isoforms, config))
+        .collect()
+}
 
-    let lines = coords
+pub fn gene_spans(isoforms: &HashMap<String, String>, gene_track: &GeneTrack) -> GeneTrack {
+    isoforms
         .par_iter()
-        .map(|(gene, (chrom, start, end, strand))| {
-            (
-                chrom.to_string(),
-                "gene".to_string(),
-                start + 1,
-                *end,
-                strand.to_string(),
-                ".".to_string(),
-                format!("ID={};gene_id={}", gene, gene),
-            )
+        .fold(HashMap::new, |mut acc: GeneTrack, (transcript, gene)| {
+            if let Some(&(ref chrom, start, end, ref strand)) = gene_track.get(transcript) {
+                let entry = acc.entry(gene.clone()).or_insert((
+                    chrom.to_string(),
+                    start,
+                    end,
+                    strand.to_string(),
+                ));
+                entry.1 = entry.1.min(start); // Update min start
+                entry.2 = entry.2.max(end); // Update max end
+            }
+            acc
         })
-        .collect();
-    lines
+        .reduce(HashMap::new, |mut a, b| {
+            for (gene, (chrom, start, end, strand)) in b {
+                let entry = a.entry(gene).or_insert((chrom, start, end, strand));
+                entry.1 = entry.1.min(start); // Update min start
+                entry.2 = entry.2.max(end); // Update max end
+            }
+            a
+        })
+}
+
+pub fn gene_line(
+    gene: &str,
+    span: &(String, u32, u32, String),
+    biotype: Option<&str>,
+    isoforms: &HashMap<String, String>,
+    config: &Config,
+) -> GffRecord {
+    let (chrom, start, end, strand) = span;
+    let mut attrs = vec![
+        ("ID", config.gene_id(gene, isoforms.contains_key(gene))),
+        ("gene_id", gene.to_string()),
+    ];
+    if let Some(name) = config.gene_names.get(gene) {
+        attrs.push(("gene_name", name.clone()));
+    }
+    if let Some(biotype) = biotype {
+        attrs.push(("gene_biotype", biotype.to_string()));
+    }
+    attrs.extend(config.xrefs(gene));
+
+    GffRecord {
+        seqid: chrom.to_string(),
+        source: config.source().to_string(),
+        feature: "gene".to_string(),
+        start: start + 1,
+        end: *end,
+        score: ".".to_string(),
+        strand: strand.to_string(),
+        phase: ".".to_string(),
+        attributes: config.format.attributes(&attrs),
+    }
+}
+
+/// Gene lines keyed by gene, spanning all of its transcripts; with empty
+/// `isoforms` each transcript is its own gene, as in `to_gff`. GTF carries
+/// genes only through the gene_id attribute, so it gets none; neither do
+/// `--no-gene` outputs and outputs whose --features leave genes out.
+pub fn gene_lines(
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    config: &Config,
+) -> HashMap<String, GffRecord> {
+    if config.no_gene || !config.emits(Feature::Gene) {
+        return HashMap::new();
+    }
+    let identity;
+    let isoforms = match isoforms.is_empty() {
+        true => {
+            identity = identity_isoforms(records);
+            &identity
+        }
+        false => isoforms,
+    };
+
+    match config.format {
+        Format::Gff3 | Format::Gff2 => {
+            let gene_track = custom_par_parse(records).unwrap_or_default();
+            let biotypes = gene_biotypes(records, isoforms, config);
+            gene_spans(isoforms, &gene_track)
+                .iter()
+                .map(|(gene, span)| {
+                    let biotype = biotypes.get(gene.as_str()).copied();
+                    (
+                        gene.clone(),
+                        gene_line(gene, span, biotype, isoforms, config),
+                    )
+                })
+                .collect()
+        }
+        Format::Gtf => HashMap::new(),
+    }
+}
+
+/// Biotype of each gene under `--biotypes`: protein_coding if any of its
+/// transcripts is, otherwise that of its first transcript with one.
+pub fn gene_biotypes<'a>(
+    records: &'a [BedRecord],
+    isoforms: &HashMap<String, String>,
+    config: &'a Config,
+) -> HashMap<String, &'a str> {
+    let mut biotypes = HashMap::new();
+    for record in records {
+        let (Some(gene), Some(biotype)) = (isoforms.get(&record.name), config.biotype(record))
+        else {
+            continue;
+        };
+        let entry = biotypes.entry(gene.clone()).or_insert(biotype);
+        if biotype == "protein_coding" {
+            *entry = biotype;
+        }
+    }
+    biotypes
+}
+
+/// Default capacity of output buffers, 1 MiB.
+pub const BUFFER_SIZE: usize = 1 << 20;
+/// Output path that writes to stdout instead of a file.
+pub const STDOUT: &str = "-";
+/// Input path that reads from stdin instead of a file.
+pub const STDIN: &str = "-";
+
+/// Writer turning every `\n` written through it into `\r\n`.
+pub struct CrlfWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> CrlfWriter<W> {
+    pub fn new(inner: W) -> Self {
+        CrlfWriter { inner }
+    }
+}
+
+impl<W: Write> Write for CrlfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut lines = buf.split(|&byte| byte == b'\n');
+        if let Some(first) = lines.next() {
+            self.inner.write_all(first)?;
+        }
+        for line in lines {
+            self.inner.write_all(b"\r\n")?;
+            self.inner.write_all(line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Opens an output for writing, at its end if `append` is set; `-` writes
+/// to stdout. Appended gzip output becomes a new gzip member, which readers
+/// decompress as one stream.
+pub fn create_writer(
+    path: &PathBuf,
+    codec: Codec,
+    buffer_size: usize,
+    append: bool,
+) -> io::Result<Box<dyn Write + Send>> {
+    let sink: Box<dyn Write + Send> = if path.as_os_str() == STDOUT {
+        Box::new(io::stdout())
+    } else if append {
+        Box::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| path_error(e, "open output file", path))?,
+        )
+    } else {
+        Box::new(File::create(path).map_err(|e| path_error(e, "create output file", path))?)
+    };
+
+    Ok(match codec {
+        Codec::None => Box::new(BufWriter::with_capacity(buffer_size, sink)),
+        Codec::Gzip => Box::new(BufWriter::with_capacity(
+            buffer_size,
+            GzEncoder::new(sink, Compression::default()),
+        )),
+        Codec::Bgzip => Box::new(BufWriter::with_capacity(
+            buffer_size,
+            noodles_bgzf::io::Writer::new(sink),
+        )),
+    })
 }
 
 pub fn max_mem_usage_mb() -> f64 {
@@ -177,8 +1018,63 @@ pub fn max_mem_usage_mb() -> f64 {
     }
 }
 
+/// Logger appending `simple_logger`-style lines to a file
+pub struct FileLogger {
+    file: Mutex<File>,
+    level: LevelFilter,
+}
+
+impl FileLogger {
+    pub fn new(path: &Path, level: LevelFilter) -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| path_error(e, "open log file", path))?;
+        Ok(FileLogger {
+            file: Mutex::new(file),
+            level,
+        })
+    }
+
+    /// Installs a `FileLogger` on `path` as the global logger
+    pub fn init(path: &Path, level: LevelFilter) -> io::Result<()> {
+        let logger = FileLogger::new(path, level)?;
+        log::set_max_level(level);
+        log::set_boxed_logger(Box::new(logger)).map_err(io::Error::other)
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {:<5} [{}] {}\n",
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
 pub fn msg() {
-    println!(
+    eprintln!(
         "{}\n{}\n{}\n",
         "\n##### BED2GFF #####".bright_blue().bold(),
         indoc!(
@@ -199,10 +1095,1074 @@ pub fn get_date() -> String {
     format!("{}-{}-{}", year, month, day)
 }
 
-pub fn comments(file: &mut Box<dyn Write>) {
-    let _ = file.write_all(format!("{}\n", GFF3).as_bytes());
-    let _ = file.write_all(format!("#provider: {}\n", SOURCE).as_bytes());
+pub fn open_gff(path: &PathBuf, gz: bool) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    if gz {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+pub fn comments<W: Write>(file: &mut W, config: &Config) {
+    if config.no_header {
+        return;
+    }
+    if let Some(pragma) = config.format.pragma() {
+        let _ = file.write_all(format!("{}\n", pragma).as_bytes());
+    }
+    let _ = file.write_all(format!("#provider: {}\n", config.source()).as_bytes());
     let _ = file.write_all(format!("#version: {}\n", VERSION).as_bytes());
     let _ = file.write_all(format!("#contact: {}\n", REPOSITORY).as_bytes());
     let _ = file.write_all(format!("#date: {}\n", get_date()).as_bytes());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, LineEnding};
+    use crate::gff::{sort_records, write_gff};
+    use crate::lines::to_gff;
+
+    /// One single-exon noncoding record on chr1:100-200 per name.
+    fn noncoding(names: &[&str]) -> Vec<BedRecord> {
+        names
+            .iter()
+            .map(|name| {
+                let line = format!("chr1\t100\t200\t{}\t0\t+\t100\t100\t0\t1\t100,\t0,", name);
+                BedRecord::parse(&line).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gene_once_across_inputs() {
+        let dir = std::env::temp_dir().join("bed2gff_gene_once_across_inputs");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.bed"), dir.join("b.bed"));
+        std::fs::write(&a, "chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,\n").unwrap();
+        std::fs::write(&b, "chr1\t300\t900\tTX2\t0\t+\t300\t300\t0\t1\t600,\t0,\n").unwrap();
+
+        let records = [a, b]
+            .iter()
+            .flat_map(|path| bed_reader(path, false, false, false, &NameSource::default()).0)
+            .collect::<Vec<_>>();
+        let isoforms = parallel_hash_rev("GENE1\tTX1\nGENE1\tTX2");
+        let genes = combine_maps_par(
+            &isoforms,
+            &custom_par_parse(&records).unwrap(),
+            &Config::default(),
+        );
+
+        assert_eq!(genes.len(), 1);
+        assert_eq!((genes[0].start, genes[0].end), (101, 900));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shards_merge_sorted_and_deduplicated() {
+        let dir = std::env::temp_dir().join("bed2gff_shards_merge_sorted_and_deduplicated");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("chr2.bed"), dir.join("chr1.bed"));
+        std::fs::write(
+            &a,
+            "chr2\t500\t900\tTX2\t0\t+\t500\t500\t0\t1\t400,\t0,\n\
+             chr2\t100\t400\tTX1\t0\t+\t100\t100\t0\t1\t300,\t0,\n",
+        )
+        .unwrap();
+        std::fs::write(&b, "chr1\t100\t300\tTX1\t0\t-\t100\t100\t0\t1\t200,\t0,\n").unwrap();
+
+        let mut records = [a, b]
+            .iter()
+            .flat_map(|path| bed_reader(path, false, false, false, &NameSource::default()).0)
+            .collect::<Vec<_>>();
+        let renamed = dedup_names(&mut records);
+        assert_eq!(renamed, vec![(2, "TX1".to_string())]);
+
+        let config = Config::default();
+        let isoforms = identity_isoforms(&records);
+        sort_records(&mut records, config.sort, &config.seqid_order);
+        let genes = gene_lines(&records, &isoforms, &config);
+        let mut out = Vec::new();
+        write_gff(&mut out, &records, &isoforms, &genes, &config).unwrap();
+
+        let gff = String::from_utf8(out).unwrap();
+        let gene_ids = gff
+            .lines()
+            .filter(|line| line.split('\t').nth(2) == Some("gene"))
+            .map(|line| {
+                let fields = line.split('\t').collect::<Vec<_>>();
+                (fields[0], fields[3], fields[8].split(';').next().unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            gene_ids,
+            vec![
+                ("chr1", "101", "ID=gene:TX1.1"),
+                ("chr2", "101", "ID=gene:TX1"),
+                ("chr2", "501", "ID=gene:TX2"),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gene_spans_union_of_isoforms() {
+        let records = [
+            "chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,",
+            "chr1\t50\t400\tTX2\t0\t+\t50\t50\t0\t1\t350,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+        let isoforms = parallel_hash_rev("GENE1\tTX1\nGENE1\tTX2");
+
+        let genes = combine_maps_par(
+            &isoforms,
+            &custom_par_parse(&records).unwrap(),
+            &Config::default(),
+        );
+
+        assert_eq!(genes.len(), 1);
+        assert_eq!((genes[0].start, genes[0].end), (51, 500));
+    }
+
+    #[test]
+    fn gzipped_bed_matches_plain() {
+        let dir = std::env::temp_dir().join("bed2gff_gzipped_bed_matches_plain");
+        std::fs::create_dir_all(&dir).unwrap();
+        let bed = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,\n";
+        let (plain, gz) = (dir.join("a.bed"), dir.join("a.bed.gz"));
+        std::fs::write(&plain, bed).unwrap();
+        let mut encoder = GzEncoder::new(File::create(&gz).unwrap(), Compression::default());
+        encoder.write_all(bed.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        assert_eq!(reader(&gz).unwrap(), reader(&plain).unwrap());
+        assert_eq!(
+            bed_reader(&gz, false, false, false, &NameSource::default()),
+            bed_reader(&plain, false, false, false, &NameSource::default())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn isoforms_from_gzip_and_stdin() {
+        let dir = std::env::temp_dir().join("bed2gff_isoforms_from_gzip_and_stdin");
+        std::fs::create_dir_all(&dir).unwrap();
+        let isoforms = "GENE1\tTX1\nGENE1\tTX2\nGENE2\tTX3\n";
+        let (plain, gz) = (dir.join("iso.txt"), dir.join("iso.txt.gz"));
+        std::fs::write(&plain, isoforms).unwrap();
+        let mut encoder = GzEncoder::new(File::create(&gz).unwrap(), Compression::default());
+        encoder.write_all(isoforms.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        // stdin goes through the same decompress() as files
+        let mut piped = String::new();
+        decompress(io::Cursor::new(isoforms.as_bytes().to_vec()))
+            .unwrap()
+            .read_to_string(&mut piped)
+            .unwrap();
+
+        let expected = get_isoforms(&reader(&plain).unwrap(), 1, 2, None, None, None).unwrap();
+        assert_eq!(
+            get_isoforms(&reader(&gz).unwrap(), 1, 2, None, None, None).unwrap(),
+            expected
+        );
+        assert_eq!(
+            get_isoforms(&piped, 1, 2, None, None, None).unwrap(),
+            expected
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writer_matches_buffer() {
+        let path = std::env::temp_dir().join("bed2gff_writer_matches_buffer.gff");
+        let line = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,";
+        let records = vec![BedRecord::parse(line).unwrap()];
+        let isoforms = parallel_hash_rev("GENE1\tTX1");
+        let genes = HashMap::new();
+        let config = Config::default();
+
+        let mut buffer = Vec::new();
+        write_gff(&mut buffer, &records, &isoforms, &genes, &config).unwrap();
+        let mut writer = create_writer(&path, Codec::None, BUFFER_SIZE, false).unwrap();
+        write_gff(&mut writer, &records, &isoforms, &genes, &config).unwrap();
+        drop(writer);
+
+        assert_eq!(std::fs::read(&path).unwrap(), buffer);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn append_keeps_one_header() {
+        let path = std::env::temp_dir().join("bed2gff_append_keeps_one_header.gff");
+        let config = Config::default();
+
+        for (i, line) in [
+            "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,",
+            "chr2\t100\t500\tTX2\t0\t+\t100\t100\t0\t1\t400,\t0,",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let records = vec![BedRecord::parse(line).unwrap()];
+            let mut writer = create_writer(&path, Codec::None, BUFFER_SIZE, i > 0).unwrap();
+            if i == 0 {
+                comments(&mut writer, &config);
+            }
+            write_gff(
+                &mut writer,
+                &records,
+                &HashMap::new(),
+                &HashMap::new(),
+                &config,
+            )
+            .unwrap();
+        }
+
+        let gff = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(gff.matches("##gff-version").count(), 1);
+        assert!(gff.contains("ID=TX1;") && gff.contains("ID=TX2;"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn malformed_lines_are_reported() {
+        let bed = "chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,
+chr1\t100\t500\tTX2\t0\t+
+chr1\t100\t500\tTX3\t0\t+\t100\t100\t0\t1\t4x0,\t0,
+chr1\t300\t900\tTX4\t0\t+\t300\t300\t0\t1\t600,\t0,";
+
+        let (records, malformed) = parse_lines(bed, BedRecord::parse);
+
+        assert_eq!(
+            records.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            vec!["TX1", "TX4"]
+        );
+        assert_eq!(
+            malformed.iter().map(|(n, _, _)| *n).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(malformed[0].1, "chr1\t100\t500\tTX2\t0\t+");
+    }
+
+    #[test]
+    fn one_gene_per_transcript_without_isoforms() {
+        let records = noncoding(&["TX1", "TX2", "TX3"]);
+        let isoforms = identity_isoforms(&records);
+
+        let mut genes = combine_maps_par(
+            &isoforms,
+            &custom_par_parse(&records).unwrap(),
+            &Config::default(),
+        )
+        .into_iter()
+        .map(|line| line.attribute("gene_id").unwrap().to_string())
+        .collect::<Vec<_>>();
+        genes.sort();
+        assert_eq!(genes, vec!["TX1", "TX2", "TX3"]);
+
+        for record in &records {
+            let lines = to_gff(record, &isoforms, &Config::default()).unwrap();
+            assert!(lines
+                .iter()
+                .all(|l| l.attribute("gene_id") == Some(record.name.as_str())));
+        }
+    }
+
+    #[test]
+    fn own_gene_ids_stay_unique() {
+        let records = noncoding(&["TX1", "TX2"]);
+        let config = Config::default();
+
+        for isoforms in [identity_isoforms(&records), HashMap::new()] {
+            let genes = gene_lines(&records, &isoforms, &config);
+            let mut out = Vec::new();
+            write_gff(&mut out, &records, &isoforms, &genes, &config).unwrap();
+            let gff = String::from_utf8(out).unwrap();
+
+            assert!(gff.contains("\tgene\t101\t200\t.\t+\t.\tID=gene:TX1;gene_id=TX1"));
+            assert!(gff.contains("ID=TX1;Parent=gene:TX1;"));
+            let problems = crate::validate::validate_gff(gff.as_bytes()).unwrap();
+            assert!(problems.is_empty(), "{:?}", problems);
+        }
+    }
+
+    #[test]
+    fn bgzip_output_reads_as_gzip() {
+        let dir = std::env::temp_dir().join("bed2gff_bgzip_output_reads_as_gzip");
+        std::fs::create_dir_all(&dir).unwrap();
+        let line = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,";
+        let records = vec![BedRecord::parse(line).unwrap()];
+        let isoforms = parallel_hash_rev("GENE1\tTX1");
+        let config = Config::default();
+
+        let write = |path: &PathBuf, codec| {
+            let mut writer = create_writer(path, codec, BUFFER_SIZE, false).unwrap();
+            write_gff(&mut writer, &records, &isoforms, &HashMap::new(), &config).unwrap();
+        };
+        let (plain, bgzip) = (dir.join("a.gff"), dir.join("a.gff.gz"));
+        write(&plain, Codec::None);
+        write(&bgzip, Codec::Bgzip);
+
+        let mut decoded = Vec::new();
+        MultiGzDecoder::new(File::open(&bgzip).unwrap())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, std::fs::read(&plain).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn codecs_round_trip() {
+        let dir = std::env::temp_dir().join("bed2gff_codecs_round_trip");
+        std::fs::create_dir_all(&dir).unwrap();
+        let line = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,";
+        let records = vec![BedRecord::parse(line).unwrap()];
+        let isoforms = parallel_hash_rev("GENE1\tTX1");
+        let config = Config::default();
+
+        let mut expected = Vec::new();
+        write_gff(&mut expected, &records, &isoforms, &HashMap::new(), &config).unwrap();
+
+        for codec in [Codec::None, Codec::Gzip, Codec::Bgzip] {
+            let path = codec.with_extension(&dir.join("a.gff"));
+            let mut writer = create_writer(&path, codec, BUFFER_SIZE, false).unwrap();
+            write_gff(&mut writer, &records, &isoforms, &HashMap::new(), &config).unwrap();
+            writer.flush().unwrap();
+            drop(writer);
+
+            let mut decoded = Vec::new();
+            open_gff(&path, codec != Codec::None)
+                .unwrap()
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, expected, "{:?}", codec);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn io_errors_name_the_file() {
+        let dir = std::env::temp_dir().join("bed2gff_io_errors_name_the_file");
+        let missing = dir.join("iso.txt");
+
+        let e = read_file(&missing, "isoforms file").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(e.to_string().starts_with(&format!(
+            "could not open isoforms file '{}': ",
+            missing.display()
+        )));
+
+        let out = dir.join("no_such_dir").join("out.gff");
+        let e = create_writer(&out, Codec::None, BUFFER_SIZE, false)
+            .err()
+            .unwrap();
+        assert!(e.to_string().starts_with(&format!(
+            "could not create output file '{}': ",
+            out.display()
+        )));
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let line = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,";
+        let records = vec![BedRecord::parse(line).unwrap()];
+        let isoforms = parallel_hash_rev("GENE1\tTX1");
+
+        let write = |line_ending: LineEnding| {
+            let config = Config {
+                line_ending,
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            let mut writer = line_ending.wrap(Box::new(&mut out));
+            comments(&mut writer, &config);
+            write_gff(&mut writer, &records, &isoforms, &HashMap::new(), &config).unwrap();
+            drop(writer);
+            String::from_utf8(out).unwrap()
+        };
+
+        let (lf, crlf) = (write(LineEnding::Lf), write(LineEnding::Crlf));
+        let lines = lf.matches('\n').count();
+        assert!(!lf.contains('\r'));
+        assert_eq!(crlf.matches("\r\n").count(), lines);
+        assert_eq!(crlf.len(), lf.len() + lines);
+        assert_eq!(crlf.replace("\r\n", "\n"), lf);
+    }
+
+    #[test]
+    fn header_lines_are_skipped() {
+        let bed = "track name=genes description=\"test\"
+browser position chr1:100-900
+chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,
+# comment
+chr1\t300\t900\tTX2\t0\t+\t300\t300\t0\t1\t600,\t0,
+chr2\t300\t900\tTX3\t0\t+\t300\t300\t0\t1\t600,\t0,";
+
+        let (records, malformed) = parse_lines(bed, BedRecord::parse);
+
+        assert_eq!(records.len(), 3);
+        assert!(malformed.is_empty());
+        assert_eq!(parallel_parse(bed).unwrap(), records);
+
+        let bed = "track\nbrowser\ttrack\ntrackX\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,
+browser_1\t100\t500\tTX2\t0\t+\t100\t100\t0\t1\t400,\t0,";
+        let records = parallel_parse(bed).unwrap();
+        assert_eq!(
+            records.iter().map(|r| r.chrom.as_str()).collect::<Vec<_>>(),
+            ["trackX", "browser_1"]
+        );
+    }
+
+    #[test]
+    fn gene_name_from_third_column() {
+        let isoforms_file = "GENE1\tTX1\tBRCA1\nGENE2\tTX2";
+        let records = noncoding(&["TX1", "TX2"]);
+        let (isoforms, gene_names) =
+            isoform_columns(isoforms_file, 1, 2, None, Some(3), None).unwrap();
+        let config = Config {
+            gene_names,
+            ..Default::default()
+        };
+
+        let genes = gene_lines(&records, &isoforms, &config);
+        assert_eq!(genes["GENE1"].attribute("gene_name"), Some("BRCA1"));
+        assert_eq!(genes["GENE2"].attribute("gene_name"), None);
+
+        let lines = to_gff(&records[0], &isoforms, &config).unwrap();
+        let transcript = lines.iter().find(|l| l.feature == "transcript").unwrap();
+        assert_eq!(transcript.attribute("gene_name"), Some("BRCA1"));
+    }
+
+    #[test]
+    fn isoforms_from_custom_columns() {
+        let tsv = "chr1\tortholog\tGENE1\tTX1\n\nchr1\tortholog\tGENE1\tTX2\n";
+        let isoforms = isoform_columns(tsv, 3, 4, None, None, None).unwrap().0;
+        assert_eq!(isoforms.len(), 2);
+        assert_eq!(isoforms["TX1"], "GENE1");
+        assert_eq!(isoforms["TX2"], "GENE1");
+
+        let csv = "GENE1,TX1\nGENE2\n";
+        assert_eq!(
+            isoform_columns(csv, 1, 2, None, None, Some(',')).unwrap_err(),
+            "isoforms line 2: expected at least 2 columns, found 1"
+        );
+    }
+
+    #[test]
+    fn gene_names_follow_isoform_columns() {
+        let csv = "TX1,BRCA1,GENE1\nTX2,,GENE2\nTX3,TP53,GENE3\n";
+        let (isoforms, names) = isoform_columns(csv, 3, 1, None, Some(2), Some(',')).unwrap();
+        assert_eq!(isoforms["TX1"], "GENE1");
+        assert_eq!(names.len(), 2);
+        assert_eq!(names["GENE1"], "BRCA1");
+        assert_eq!(names["GENE3"], "TP53");
+
+        let (_, names) = isoform_columns("GENE1\tTX1\tBRCA1", 1, 2, None, None, None).unwrap();
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn genes_from_name_regex() {
+        let records = noncoding(&["FOO.1", "FOO.2", "BAR.1", "-baz"]);
+
+        let (isoforms, unmatched) = regex_isoforms(&records, &Regex::new("^([^.-]+)").unwrap());
+        assert_eq!(isoforms["FOO.1"], "FOO");
+        assert_eq!(isoforms["FOO.2"], "FOO");
+        assert_eq!(isoforms["BAR.1"], "BAR");
+        assert_eq!(isoforms["-baz"], "-baz");
+        assert_eq!(unmatched, vec!["-baz"]);
+    }
+
+    #[test]
+    fn gene_biotype_prefers_coding() {
+        let records = [
+            "chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,",
+            "chr1\t200\t600\tTX2\t0\t+\t250\t550\t0\t1\t400,\t0,",
+            "chr1\t700\t900\tTX3\t0\t+\t700\t700\t0\t1\t200,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+        let isoforms = parallel_hash_rev("GENE1\tTX1\nGENE1\tTX2\nGENE2\tTX3");
+        let config = Config {
+            biotypes: Some(parallel_hash("TX1\tretained_intron\nTX3\tlncRNA")),
+            ..Default::default()
+        };
+
+        let genes = gene_lines(&records, &isoforms, &config);
+        assert_eq!(
+            genes["GENE1"].attribute("gene_biotype"),
+            Some("protein_coding")
+        );
+        assert_eq!(genes["GENE2"].attribute("gene_biotype"), Some("lncRNA"));
+    }
+
+    #[test]
+    fn long_introns_filtered() {
+        let mut records = [
+            "chr1\t100\t2000600\tTX1\t0\t+\t100\t100\t0\t2\t100,100,\t0,2000400,",
+            "chr1\t100\t600\tTX2\t0\t+\t100\t100\t0\t2\t100,100,\t0,400,",
+            "chr1\t100\t200\tTX3\t0\t+\t100\t100\t0\t1\t100,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+
+        let dropped = filter_introns(&mut records, 1_000_000);
+        assert_eq!(dropped, [("TX1".to_string(), 2_000_300)]);
+        assert_eq!(
+            records.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            ["TX2", "TX3"]
+        );
+    }
+
+    #[test]
+    fn manifest_counts() {
+        let dir = std::env::temp_dir().join("bed2gff_manifest_counts");
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("manifest.json");
+
+        let bed = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,
+chr1\t600\t900\tTX2\t0\t-\t600\t600\t0\t1\t300,\t0,";
+        let mut out = Vec::new();
+        let stats = crate::gff::bed2gff_stream(
+            bed.as_bytes(),
+            Some("GENE1\tTX1\nGENE2\tTX2".as_bytes()),
+            &mut out,
+            &Config::default(),
+        )
+        .unwrap();
+
+        let input = PathBuf::from("in.bed");
+        write_manifest(
+            &manifest,
+            &[&input],
+            &[PathBuf::from("out.gff")],
+            &stats,
+            1,
+            2,
+            0.5,
+        )
+        .unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&manifest).unwrap()).unwrap();
+        assert_eq!(json["version"], VERSION);
+        assert_eq!(json["inputs"], json!(["in.bed"]));
+        assert_eq!(json["outputs"], json!(["out.gff"]));
+        assert_eq!(
+            json["counts"],
+            json!({"genes": 2, "transcripts": 2, "exons": 3, "cds": 2, "utr": 2, "skipped": 0})
+        );
+        assert_eq!(
+            (json["malformed"].clone(), json["unmapped"].clone()),
+            (json!(1), json!(2))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn isoform_priorities() {
+        let isoforms = "GENE1\tTX1\t5\nGENE2\tTX1\t2\nGENE3\tTX2\t1\nGENE4\tTX2\t3\nGENE5\tTX3\t1";
+        let map = isoform_columns(isoforms, 1, 2, Some(3), None, None)
+            .unwrap()
+            .0;
+        assert_eq!(map["TX1"], "GENE1");
+        assert_eq!(map["TX2"], "GENE4");
+        assert_eq!(map["TX3"], "GENE5");
+
+        // without priorities the last mapping wins
+        let map = isoform_columns(isoforms, 1, 2, None, None, None).unwrap().0;
+        assert_eq!(map["TX1"], "GENE2");
+
+        assert_eq!(
+            isoform_columns("GENE1\tTX1\thigh", 1, 2, Some(3), None, None).unwrap_err(),
+            "isoforms line 1: invalid priority \"high\""
+        );
+    }
+
+    #[test]
+    fn gene_across_chromosomes_is_split() {
+        let records = [
+            "chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,",
+            "chr2\t200\t600\tTX2\t0\t+\t200\t200\t0\t1\t400,\t0,",
+            "chr1\t700\t900\tTX3\t0\t+\t700\t700\t0\t1\t200,\t0,",
+            "chr1\t100\t500\tTX4\t0\t+\t100\t100\t0\t1\t400,\t0,",
+            "chr1\t700\t900\tTX5\t0\t-\t700\t700\t0\t1\t200,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+        let mut isoforms =
+            parallel_hash_rev("GENE1\tTX1\nGENE1\tTX2\nGENE1\tTX3\nGENE2\tTX4\nGENE2\tTX5");
+
+        let split = split_genes(&records, &mut isoforms);
+        assert_eq!(
+            split,
+            [
+                (
+                    "GENE1".to_string(),
+                    vec!["GENE1.chr1".to_string(), "GENE1.chr2".to_string()]
+                ),
+                (
+                    "GENE2".to_string(),
+                    vec!["GENE2.minus".to_string(), "GENE2.plus".to_string()]
+                ),
+            ]
+        );
+        assert_eq!(isoforms["TX1"], "GENE1.chr1");
+        assert_eq!(isoforms["TX2"], "GENE1.chr2");
+        assert_eq!(isoforms["TX3"], "GENE1.chr1");
+        assert_eq!(isoforms["TX5"], "GENE2.minus");
+
+        let genes = gene_lines(&records, &isoforms, &Config::default());
+        assert_eq!(genes["GENE1.chr2"].seqid, "chr2");
+        assert_eq!(
+            (genes["GENE1.chr1"].start, genes["GENE1.chr1"].end),
+            (101, 900)
+        );
+        assert!(split_genes(&records, &mut isoforms).is_empty());
+    }
+
+    #[test]
+    fn crlf_isoforms() {
+        let crlf = "GENE1\tTX1\r\n\r\nGENE1\tTX2\r\nGENE2\tTX3\r";
+        let plain = "GENE1\tTX1\nGENE1\tTX2\nGENE2\tTX3\n";
+
+        for delim in [None, Some('\t')] {
+            assert_eq!(
+                isoform_columns(crlf, 1, 2, None, None, delim).unwrap().0,
+                isoform_columns(plain, 1, 2, None, None, delim).unwrap().0
+            );
+        }
+        assert_eq!(
+            isoform_columns(crlf, 1, 2, None, None, None).unwrap().0["TX3"],
+            "GENE2"
+        );
+    }
+
+    #[test]
+    fn unmapped_transcripts_are_dropped() {
+        let mut records = noncoding(&["TX1", "TX2", "TX3"]);
+        let isoforms = parallel_hash_rev("GENE1\tTX1\nGENE3\tTX3");
+
+        assert!(to_gff(&records[1], &isoforms, &Config::default()).is_err());
+        assert_eq!(drop_unmapped(&mut records, &isoforms), vec!["TX2"]);
+        assert_eq!(gene_lines(&records, &isoforms, &Config::default()).len(), 2);
+    }
+
+    #[test]
+    fn unmapped_names_are_dumped() {
+        let mut records = noncoding(&["TX1", "TX2", "TX3", "TX4", "TX5"]);
+        let isoforms = parallel_hash_rev("GENE1\tTX1\nGENE1\tTX3\nGENE2\tTX5");
+        let path = std::env::temp_dir().join("bed2gff_unmapped_names_are_dumped.txt");
+
+        write_names(&path, &drop_unmapped(&mut records, &isoforms)).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "TX2\nTX4\n");
+        assert_eq!(records.len(), 3);
+    }
+
+    #[test]
+    fn unmapped_transcript_is_a_problem() {
+        let records = noncoding(&["TX1", "TX2"]);
+
+        assert!(input_problems(&records, &HashMap::new()).is_empty());
+        assert_eq!(
+            input_problems(&records, &parallel_hash_rev("GENE1\tTX1")),
+            vec!["TX2: not found in isoforms file"]
+        );
+    }
+
+    #[test]
+    fn transcript_beyond_chrom_end() {
+        let records = [
+            "chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,",
+            "chr1\t800\t1200\tTX2\t0\t+\t800\t800\t0\t1\t400,\t0,",
+            "chr2\t800\t1200\tTX3\t0\t+\t800\t800\t0\t1\t400,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+        // .fai: extra columns after the length are ignored
+        let sizes = get_chrom_sizes("chr1\t1000\t6\t60\t61\nchr2\t2000\t1030\t60\t61\n");
+
+        assert_eq!(sizes["chr1"], 1000);
+        assert_eq!(check_bounds(&records, &sizes), 1);
+    }
+
+    #[test]
+    fn header_version_is_crate_version() {
+        let mut out = Vec::new();
+        comments(&mut out, &Config::default());
+
+        let header = String::from_utf8(out).unwrap();
+        assert!(header
+            .lines()
+            .any(|line| line == format!("#version: {}", env!("CARGO_PKG_VERSION"))));
+    }
+
+    #[test]
+    fn duplicate_names_get_suffixes() {
+        let mut records = noncoding(&["TX1", "TX2", "TX1", "TX1.1", "TX1"]);
+
+        let renamed = dedup_names(&mut records);
+
+        assert_eq!(
+            renamed,
+            vec![(2, "TX1".to_string()), (4, "TX1".to_string())]
+        );
+        assert_eq!(
+            records.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            vec!["TX1", "TX2", "TX1.2", "TX1.1", "TX1.3"]
+        );
+    }
+
+    #[test]
+    fn chroms_are_mapped() {
+        let mut records = ["1", "MT", "GL000192.1"]
+            .iter()
+            .map(|chrom| {
+                let line = format!(
+                    "{}\t100\t500\tTX{}\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,",
+                    chrom, chrom
+                );
+                BedRecord::parse(&line).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let map = parallel_hash("1\tchr1\nMT\tchrM");
+
+        assert_eq!(map_chroms(&mut records, &map), vec!["GL000192.1"]);
+
+        let isoforms = identity_isoforms(&records);
+        let lines = to_gff(&records[0], &isoforms, &Config::default()).unwrap();
+        assert!(lines.iter().all(|line| line.seqid == "chr1"));
+        assert_eq!(records[1].chrom, "chrM");
+        assert_eq!(records[2].chrom, "GL000192.1");
+    }
+
+    #[test]
+    fn single_exon_transcripts_filtered() {
+        let mut records = [
+            "chr1\t100\t500\tTX1\t0\t+\t100\t100\t0\t1\t400,\t0,",
+            "chr1\t100\t500\tTX2\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,",
+            "chr1\t600\t900\tTX3\t0\t+\t600\t600\t0\t1\t300,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+        let isoforms = parallel_hash_rev("GENE1\tTX1\nGENE1\tTX2\nGENE2\tTX3");
+
+        assert_eq!(filter_exons(&mut records, Some(2), None), 2);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, "TX2");
+
+        // GENE2 lost its only transcript and gets no gene line
+        let genes = gene_lines(&records, &isoforms, &Config::default());
+        assert_eq!(genes.keys().collect::<Vec<_>>(), vec!["GENE1"]);
+    }
+
+    #[test]
+    fn unmapped_by_suffix() {
+        let records = noncoding(&["TX1", "TX2PG", "TX3PG", "TX4PM", "TX5L", "TX6X"]);
+        let isoforms = parallel_hash_rev("GENE1\tTX1");
+        let categories = vec!["PG".to_string(), "PM".to_string(), "L".to_string()];
+
+        let report = unmapped_categories(&records, &isoforms, &categories, CategoryMatch::Suffix);
+
+        assert_eq!(
+            report,
+            vec![
+                ("PG".to_string(), 2),
+                ("PM".to_string(), 1),
+                ("L".to_string(), 1),
+                ("other".to_string(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_transcripts_keep_links() {
+        let line = "chr15\t81000922\t81005788\tTX_0001\t0\t+\t81002271\t81003360\t0\t1\t4866,\t0,";
+        let mut records = vec![BedRecord::parse(line).unwrap()];
+        let mut isoforms = parallel_hash_rev("GENE1\tTX_0001");
+        let names = parallel_hash("TX_0001\tcleanName");
+
+        rename_transcripts(&mut records, &mut isoforms, &names);
+        let lines = to_gff(&records[0], &isoforms, &Config::default()).unwrap();
+
+        let transcript = lines.iter().find(|l| l.feature == "transcript").unwrap();
+        assert_eq!(transcript.attribute("ID"), Some("cleanName"));
+        assert_eq!(transcript.attribute("Parent"), Some("GENE1"));
+
+        for line in lines.iter().filter(|l| l.feature != "transcript") {
+            assert_eq!(line.attribute("Parent"), Some("cleanName"));
+            assert_eq!(line.attribute("transcript_id"), Some("cleanName"));
+            assert_eq!(line.attribute("gene_id"), Some("GENE1"));
+        }
+    }
+
+    #[test]
+    fn canonical_isoform_per_gene() {
+        let lines = [
+            // 300 exonic bp, 150 coding
+            "chr1\t100\t600\tTX1\t0\t+\t150\t500\t0\t2\t100,200,\t0,300,",
+            // 400 exonic bp, 90 coding
+            "chr1\t100\t500\tTX2\t0\t+\t200\t290\t0\t1\t400,\t0,",
+            // 400 exonic bp, non-coding
+            "chr1\t50\t450\tTX3\t0\t+\t50\t50\t0\t1\t400,\t0,",
+            "chr2\t100\t200\tTX4\t0\t+\t100\t100\t0\t1\t100,\t0,",
+        ];
+        let isoforms = parallel_hash_rev("GENE1\tTX1\nGENE1\tTX2\nGENE1\tTX3");
+
+        let canonical = |mode| {
+            let mut records = lines
+                .iter()
+                .map(|line| BedRecord::parse(line).unwrap())
+                .collect::<Vec<_>>();
+            let dropped = canonical_isoforms(&mut records, &isoforms, mode);
+            assert_eq!(dropped, 2);
+            records
+        };
+
+        assert_eq!(canonical(Canonical::LongestCds)[0].name, "TX1");
+
+        let records = canonical(Canonical::LongestTx);
+        let config = Config::default();
+        let genes = gene_lines(&records, &isoforms, &config);
+        let mut out = Vec::new();
+        write_gff(&mut out, &records, &isoforms, &genes, &config).unwrap();
+        let gff = String::from_utf8(out).unwrap();
+        let lines = gff
+            .lines()
+            .filter(|line| line.starts_with("chr1\t"))
+            .filter(|line| matches!(line.split('\t').nth(2), Some("gene" | "transcript")))
+            .map(|line| line.split('\t').nth(8).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("ID=GENE1;"));
+        assert!(lines[1].starts_with("ID=TX2;"));
+    }
+
+    #[test]
+    fn parallel_parse_matches_sequential() {
+        let bed = (0..5000)
+            .map(|i| match i % 997 {
+                0 => format!("chr{}\tbroken", i % 7),
+                _ => format!(
+                    "chr{}\t{}\t{}\tTX{}\t0\t+\t{}\t{}\t0\t1\t300,\t0,",
+                    i % 7,
+                    (i * 37) % 10000,
+                    (i * 37) % 10000 + 300,
+                    i,
+                    (i * 37) % 10000,
+                    (i * 37) % 10000
+                ),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let (mut parallel, malformed) = parse_lines(&bed, BedRecord::parse);
+        let mut sequential = Vec::new();
+        let mut failed = Vec::new();
+        for (idx, line) in bed.lines().enumerate() {
+            match BedRecord::parse(line) {
+                Ok(record) => sequential.push(record),
+                Err(_) => failed.push(idx + 1),
+            }
+        }
+
+        assert_eq!(
+            malformed.iter().map(|(n, _, _)| *n).collect::<Vec<_>>(),
+            failed
+        );
+        let config = Config::default();
+        sort_records(&mut parallel, config.sort, &config.seqid_order);
+        sort_records(&mut sequential, config.sort, &config.seqid_order);
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn file_logger_filters_by_level() {
+        let dir = std::env::temp_dir().join("bed2gff_file_logger_filters_by_level");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.log");
+        let _ = std::fs::remove_file(&path);
+
+        let logger = FileLogger::new(&path, LevelFilter::Error).unwrap();
+        for (level, message) in [
+            (log::Level::Info, "Elapsed: 1.0000 secs"),
+            (log::Level::Error, "conversion failed"),
+        ] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .target("bed2gff")
+                    .args(format_args!("{}", message))
+                    .build(),
+            );
+        }
+        logger.flush();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(log.lines().count(), 1);
+        assert!(log.ends_with(" ERROR [bed2gff] conversion failed\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn xrefs_on_genes_and_transcripts() {
+        let xrefs = xref_map(
+            "GENE1\tGeneID:1234\nTX1\tRefSeq:NM_1,UniProt:P1\nGENE1\tHGNC:5\nGENE1\tGO:0005634\tOntology_term\n",
+        );
+        let config = Config {
+            xrefs,
+            ..Default::default()
+        };
+        let line = "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,";
+        let records = vec![BedRecord::parse(line).unwrap()];
+        let isoforms = parallel_hash_rev("GENE1\tTX1");
+
+        let genes = gene_lines(&records, &isoforms, &config);
+        assert_eq!(
+            genes["GENE1"].attributes,
+            "ID=GENE1;gene_id=GENE1;Dbxref=GeneID:1234,HGNC:5;Ontology_term=GO:0005634"
+        );
+
+        let lines = to_gff(&records[0], &isoforms, &config).unwrap();
+        for line in &lines {
+            let dbxref = line.attribute("Dbxref");
+            match line.feature.as_str() {
+                "transcript" => assert_eq!(dbxref, Some("RefSeq:NM_1,UniProt:P1")),
+                _ => assert_eq!(dbxref, None),
+            }
+            assert_eq!(line.attribute("Ontology_term"), None);
+        }
+    }
+
+    #[test]
+    fn noncoding_biotype_default() {
+        let records = [
+            "chr1\t100\t500\tTX1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,",
+            "chr1\t600\t900\tTX2\t0\t+\t600\t600\t0\t1\t300,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+        let isoforms = parallel_hash_rev("GENE1\tTX1\nGENE2\tTX2");
+        let biotypes = |config: &Config| {
+            records
+                .iter()
+                .map(|record| {
+                    let lines = to_gff(record, &isoforms, config).unwrap();
+                    lines[0].attribute("transcript_biotype").map(str::to_owned)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(biotypes(&Config::default()), [None, None]);
+
+        let config = Config {
+            noncoding_biotype: Some("lncRNA".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            biotypes(&config),
+            [
+                Some("protein_coding".to_string()),
+                Some("lncRNA".to_string())
+            ]
+        );
+        let genes = gene_lines(&records, &isoforms, &config);
+        assert_eq!(genes["GENE2"].attribute("gene_biotype"), Some("lncRNA"));
+
+        let config = Config {
+            biotypes: Some(parallel_hash("TX2\tsnRNA")),
+            ..config
+        };
+        assert_eq!(biotypes(&config)[1].as_deref(), Some("snRNA"));
+    }
+
+    #[test]
+    fn max_records_limits_transcripts() {
+        let mut records = (0..5)
+            .map(|i| {
+                let line = format!(
+                    "chr1\t{}\t{}\tTX{}\t0\t+\t{}\t{}\t0\t1\t300,\t0,",
+                    i * 1000,
+                    i * 1000 + 300,
+                    i,
+                    i * 1000,
+                    i * 1000 + 300
+                );
+                BedRecord::parse(&line).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let config = Config::default();
+        sort_records(&mut records, config.sort, &config.seqid_order);
+
+        assert_eq!(head_records(&mut records, 2), 3);
+        let isoforms = identity_isoforms(&records);
+        let genes = gene_lines(&records, &isoforms, &config);
+        let mut out = Vec::new();
+        write_gff(&mut out, &records, &isoforms, &genes, &config).unwrap();
+
+        let gff = String::from_utf8(out).unwrap();
+        let transcripts = gff
+            .lines()
+            .filter(|line| line.split('\t').nth(2) == Some("transcript"))
+            .map(|line| line.split('\t').nth(8).unwrap().split(';').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(transcripts, ["ID=TX0", "ID=TX1"]);
+        assert!(!gff.contains("TX2"));
+        assert_eq!(head_records(&mut records, 10), 0);
+    }
+
+    #[test]
+    fn genes_from_name2() {
+        // bigGenePred: name2, cdsStartStat, cdsEndStat after the BED12 columns
+        let records = [
+            "chr1\t100\t500\tNM_1\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,\tBRCA1\tcmpl\tcmpl",
+            "chr1\t100\t600\tNM_2\t0\t+\t150\t450\t0\t2\t100,300,\t0,200,\tBRCA1\tcmpl\tcmpl",
+            "chr1\t900\t1000\tNR_3\t0\t+\t900\t900\t0\t1\t100,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+
+        let (isoforms, missing) = name2_isoforms(&records);
+        assert_eq!(isoforms["NM_1"], "BRCA1");
+        assert_eq!(isoforms["NM_2"], "BRCA1");
+        assert_eq!(isoforms["NR_3"], "NR_3");
+        assert_eq!(missing, ["NR_3"]);
+
+        let genes = gene_lines(&records, &isoforms, &Config::default());
+        assert_eq!(genes.len(), 2);
+        assert_eq!((genes["BRCA1"].start, genes["BRCA1"].end), (101, 600));
+    }
+
+    #[test]
+    fn short_cds_dropped() {
+        let mut records = [
+            // 150 bp CDS over two exons
+            "chr1\t100\t600\tTX1\t0\t+\t150\t500\t0\t2\t100,200,\t0,300,",
+            // 300 bp CDS
+            "chr1\t100\t500\tTX2\t0\t+\t150\t450\t0\t1\t400,\t0,",
+            "chr1\t100\t200\tTX3\t0\t+\t100\t100\t0\t1\t100,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+
+        let dropped = filter_cds_length(&mut records, 300);
+        assert_eq!(dropped, [("TX1".to_string(), 150)]);
+        let names = records
+            .iter()
+            .map(|record| record.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["TX2", "TX3"]);
+    }
+}
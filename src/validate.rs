@@ -0,0 +1,148 @@
+use crate::gff::GffRecord;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+const STRANDS: [&str; 4] = ["+", "-", ".", "?"];
+const PHASES: [&str; 3] = ["0", "1", "2"];
+
+/// Checks a GFF3 stream and returns its problems in line order, prefixed
+/// with the 1-based line number. Verified: 9 columns per line, coordinates,
+/// strand and phase legality, duplicate IDs (lines sharing an ID must share
+/// a type, as discontinuous features do), and that every Parent resolves to
+/// an ID whose span and sequence contain the child.
+pub fn validate_gff<R: BufRead>(reader: R) -> io::Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let mut records = Vec::new();
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        let n = idx + 1;
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let record = match line.parse::<GffRecord>() {
+            Ok(record) => record,
+            Err(e) => {
+                problems.push((n, e));
+                continue;
+            }
+        };
+
+        if record.start > record.end {
+            problems.push((
+                n,
+                format!("start {} is after end {}", record.start, record.end),
+            ));
+        }
+
+        if !STRANDS.contains(&record.strand.as_str()) {
+            problems.push((n, format!("invalid strand {:?}", record.strand)));
+        }
+
+        if record.feature.eq_ignore_ascii_case("CDS") && !PHASES.contains(&record.phase.as_str()) {
+            problems.push((n, format!("CDS has invalid phase {:?}", record.phase)));
+        } else if record.phase != "." && !PHASES.contains(&record.phase.as_str()) {
+            problems.push((n, format!("invalid phase {:?}", record.phase)));
+        }
+
+        records.push((n, record));
+    }
+
+    // ID -> (type, seqid, start, end) spanning every line of the feature
+    let mut ids: HashMap<&str, (&str, &str, u32, u32)> = HashMap::new();
+    for (n, record) in &records {
+        let Some(id) = record.attribute("ID") else {
+            continue;
+        };
+
+        match ids.get_mut(id) {
+            Some(entry) if entry.0 != record.feature || entry.1 != record.seqid => {
+                problems.push((*n, format!("duplicate ID {}", id)));
+            }
+            Some(entry) => {
+                entry.2 = entry.2.min(record.start);
+                entry.3 = entry.3.max(record.end);
+            }
+            None => {
+                ids.insert(
+                    id,
+                    (&record.feature, &record.seqid, record.start, record.end),
+                );
+            }
+        }
+    }
+
+    for (n, record) in &records {
+        let Some(parents) = record.attribute("Parent") else {
+            continue;
+        };
+
+        for parent in parents.split(',') {
+            if record.attribute("ID") == Some(parent) {
+                problems.push((*n, format!("{} is its own Parent", parent)));
+                continue;
+            }
+
+            match ids.get(parent) {
+                None => problems.push((*n, format!("Parent {} has no matching ID", parent))),
+                Some((_, seqid, start, end)) => {
+                    if *seqid != record.seqid || record.start < *start || record.end > *end {
+                        problems.push((
+                            *n,
+                            format!("feature lies outside its Parent {} span", parent),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    problems.sort_by_key(|(n, _)| *n);
+    Ok(problems
+        .into_iter()
+        .map(|(n, problem)| format!("line {}: {}", n, problem))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_gff() {
+        let gff = "##gff-version 3
+chr1\tbed2gff\tgene\t1\t100\t.\t+\t.\tID=G1;gene_id=G1
+chr1\tbed2gff\ttranscript\t1\t100\t.\t+\t.\tID=T1;Parent=G1
+chr1\tbed2gff\tCDS\t10\t20\t.\t+\t0\tID=CDS:T1;Parent=T1
+chr1\tbed2gff\tCDS\t40\t60\t.\t+\t2\tID=CDS:T1;Parent=T1
+";
+        assert!(validate_gff(gff.as_bytes()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_problems() {
+        let gff = "chr1\tbed2gff\tgene\t1\t100\t.\t+\t.\tID=G1
+chr1\tbed2gff\ttranscript\t1\t200\t.\t+\t.\tID=T1;Parent=G1
+chr1\tbed2gff\texon\t1\t50\t.\t+\t.\tID=T1;Parent=T2
+chr1\tbed2gff\tCDS\t10\t20\t.\t*\t.\tID=CDS:T1;Parent=T1
+chr1\tbed2gff\texon\t1\t50\t.\t+
+chr1\tbed2gff\ttranscript\t1\t100\t.\t+\t.\tID=T3;Parent=T3
+";
+        assert_eq!(
+            validate_gff(gff.as_bytes()).unwrap(),
+            vec![
+                "line 2: feature lies outside its Parent G1 span",
+                "line 3: duplicate ID T1",
+                "line 3: Parent T2 has no matching ID",
+                "line 4: invalid strand \"*\"",
+                "line 4: CDS has invalid phase \".\"",
+                "line 5: expected 9 columns, found 7",
+                "line 6: T3 is its own Parent",
+            ]
+        );
+    }
+}